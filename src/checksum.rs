@@ -0,0 +1,100 @@
+//! Checksum helpers used to verify extracted data.
+
+use std::io::Write;
+
+/// CRC-32 (IEEE 802.3) lookup table, computed at compile time.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Writer that computes the CRC-32 of everything written through it.
+///
+/// Data is forwarded to the inner writer unchanged.
+pub(crate) struct Crc32Writer<W: Write> {
+    inner: W,
+    crc: u32,
+}
+
+impl<W: Write> Crc32Writer<W> {
+    /// Wrap `inner`, starting with an empty checksum.
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            crc: 0xFFFF_FFFF,
+        }
+    }
+
+    /// The CRC-32 of the data written so far.
+    pub(crate) fn crc(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        for &byte in &buf[..written] {
+            self.crc = CRC32_TABLE[((self.crc ^ byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crc32(chunks: &[&[u8]]) -> u32 {
+        let mut writer = Crc32Writer::new(Vec::new());
+        for chunk in chunks {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.crc()
+    }
+
+    #[test]
+    fn empty_input() {
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn known_answer() {
+        assert_eq!(crc32(&[b"123456789"]), 0xCBF4_3926);
+        assert_eq!(
+            crc32(&[b"The quick brown fox jumps over the lazy dog"]),
+            0x414F_A339
+        );
+    }
+
+    #[test]
+    fn split_writes() {
+        assert_eq!(crc32(&[b"1234", b"", b"56789"]), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn forwards_data() {
+        let mut writer = Crc32Writer::new(Vec::new());
+        writer.write_all(b"123456789").unwrap();
+        assert_eq!(writer.inner, b"123456789");
+    }
+}
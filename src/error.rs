@@ -1,24 +1,152 @@
 //! Error types for archive operations.
 
 use std::fmt;
+use std::sync::Arc;
 
 /// Result type for archive operations.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Shared, cloneable source error.
+pub type ErrorSource = Arc<dyn std::error::Error + Send + Sync + 'static>;
+
 /// Errors that can occur during archive operations.
+///
+/// Any error can be annotated with the item or archive position where it
+/// occurred and with an underlying source error:
+///
+/// ```rust,ignore
+/// return Err(Error::ChecksumMismatch.at_index(index).at_path(&item.name));
+/// ```
+///
+/// Use [`Error::root()`] to get the typed error behind the annotations.
 #[derive(Debug, Clone)]
 #[non_exhaustive]
 pub enum Error {
-    /// The archive format is not recognized or is corrupted.
+    /// The archive is in this format, but its headers are corrupted.
+    ///
+    /// Use [`Error::NotArchive`] when the data is not in this format at all.
     InvalidFormat(String),
     /// An I/O error occurred.
-    Io(String),
+    Io(Arc<std::io::Error>),
     /// The requested item index is out of bounds.
     IndexOutOfBounds { index: usize, count: usize },
     /// A required feature is not supported.
     NotSupported(String),
+    /// The item uses a compression or encryption method this plugin cannot decode.
+    UnsupportedMethod(String),
+    /// Extracted data does not match its stored checksum.
+    ChecksumMismatch,
+    /// The password is wrong (or missing) for encrypted data.
+    WrongPassword,
+    /// The item's data is not available (e.g. it lives in a missing volume).
+    Unavailable,
+    /// The data ended before the item was complete.
+    UnexpectedEof,
+    /// There is extra data after the end of the item's data.
+    DataAfterEnd,
+    /// The data is not an archive of this format.
+    ///
+    /// Returned from `open()`, this lets 7-Zip try its other handlers.
+    NotArchive,
+    /// Not enough memory to complete the operation.
+    OutOfMemory,
+    /// The user cancelled the operation in 7-Zip.
+    Cancelled,
     /// Generic error with a message.
     Other(String),
+    /// An error annotated with where it happened and what caused it.
+    Context(Box<ErrorContext>),
+}
+
+/// Location and cause attached to an [`Error`].
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    /// The underlying typed error.
+    pub error: Error,
+    /// Index of the item being processed.
+    pub index: Option<usize>,
+    /// Path of the item being processed.
+    pub path: Option<String>,
+    /// Offset within the archive where the error was detected.
+    pub offset: Option<u64>,
+    /// The error that caused this one.
+    pub source: Option<ErrorSource>,
+}
+
+impl Error {
+    /// An I/O error with a message, as `Error::Io(String)` was built before
+    /// it carried the `io::Error` itself.
+    pub fn io(msg: impl Into<String>) -> Self {
+        Error::Io(Arc::new(std::io::Error::other(msg.into())))
+    }
+
+    /// The typed error, looking through any context annotations.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context(ctx) => ctx.error.root(),
+            other => other,
+        }
+    }
+
+    /// Index of the item this error refers to, if known.
+    pub fn index(&self) -> Option<usize> {
+        self.context().and_then(|ctx| ctx.index)
+    }
+
+    /// Path of the item this error refers to, if known.
+    pub fn path(&self) -> Option<&str> {
+        self.context().and_then(|ctx| ctx.path.as_deref())
+    }
+
+    /// Archive offset where this error was detected, if known.
+    pub fn offset(&self) -> Option<u64> {
+        self.context().and_then(|ctx| ctx.offset)
+    }
+
+    /// Record the index of the item being processed.
+    pub fn at_index(self, index: usize) -> Self {
+        self.with_context(|ctx| ctx.index = Some(index))
+    }
+
+    /// Record the path of the item being processed.
+    pub fn at_path(self, path: impl Into<String>) -> Self {
+        let path = path.into();
+        self.with_context(|ctx| ctx.path = Some(path))
+    }
+
+    /// Record the archive offset where the error was detected.
+    pub fn at_offset(self, offset: u64) -> Self {
+        self.with_context(|ctx| ctx.offset = Some(offset))
+    }
+
+    /// Attach the error that caused this one.
+    pub fn with_source(self, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        let source: ErrorSource = Arc::new(source);
+        self.with_context(|ctx| ctx.source = Some(source))
+    }
+
+    fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::Context(ctx) => Some(ctx),
+            _ => None,
+        }
+    }
+
+    /// Update the existing context, or wrap this error in a new one.
+    fn with_context(self, update: impl FnOnce(&mut ErrorContext)) -> Self {
+        let mut ctx = match self {
+            Error::Context(ctx) => ctx,
+            error => Box::new(ErrorContext {
+                error,
+                index: None,
+                path: None,
+                offset: None,
+                source: None,
+            }),
+        };
+        update(&mut ctx);
+        Error::Context(ctx)
+    }
 }
 
 impl fmt::Display for Error {
@@ -30,16 +158,59 @@ impl fmt::Display for Error {
                 write!(f, "Index {} out of bounds (count: {})", index, count)
             }
             Error::NotSupported(msg) => write!(f, "Not supported: {}", msg),
+            Error::UnsupportedMethod(method) => write!(f, "Unsupported method: {}", method),
+            Error::ChecksumMismatch => write!(f, "Checksum mismatch"),
+            Error::WrongPassword => write!(f, "Wrong password"),
+            Error::Unavailable => write!(f, "Data is unavailable"),
+            Error::UnexpectedEof => write!(f, "Unexpected end of data"),
+            Error::DataAfterEnd => write!(f, "There is data after the end of the payload"),
+            Error::NotArchive => write!(f, "Not an archive"),
+            Error::OutOfMemory => write!(f, "Out of memory"),
+            Error::Cancelled => write!(f, "Operation cancelled"),
             Error::Other(msg) => write!(f, "{}", msg),
+            Error::Context(ctx) => {
+                write!(f, "{}", ctx.error)?;
+                if let Some(index) = ctx.index {
+                    write!(f, " (item {})", index)?;
+                }
+                if let Some(path) = &ctx.path {
+                    write!(f, " in '{}'", path)?;
+                }
+                if let Some(offset) = ctx.offset {
+                    write!(f, " at offset {:#x}", offset)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err.as_ref()),
+            Error::Context(ctx) => match &ctx.source {
+                Some(source) => Some(source.as_ref()),
+                None => ctx.error.source(),
+            },
+            _ => None,
+        }
+    }
+}
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
-        Error::Io(err.to_string())
+        // An Error that was passed through an io::Error (e.g. from a reader or
+        // writer provided by the framework) comes back unchanged
+        if let Some(inner) = err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            return inner.clone();
+        }
+
+        match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => Error::UnexpectedEof.with_source(err),
+            std::io::ErrorKind::OutOfMemory => Error::OutOfMemory.with_source(err),
+            _ => Error::Io(Arc::new(err)),
+        }
     }
 }
 
@@ -54,3 +225,74 @@ impl From<&str> for Error {
         Error::Other(msg.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_is_merged() {
+        let err = Error::ChecksumMismatch
+            .at_index(3)
+            .at_path("dir/a.txt")
+            .at_offset(0x40);
+
+        assert!(matches!(err.root(), Error::ChecksumMismatch));
+        assert_eq!(err.index(), Some(3));
+        assert_eq!(err.path(), Some("dir/a.txt"));
+        assert_eq!(err.offset(), Some(0x40));
+        // Annotations update one context instead of nesting
+        assert!(matches!(&err, Error::Context(ctx) if !matches!(ctx.error, Error::Context(_))));
+    }
+
+    #[test]
+    fn later_annotation_wins() {
+        let err = Error::Unavailable.at_index(1).at_index(2);
+        assert_eq!(err.index(), Some(2));
+    }
+
+    #[test]
+    fn plain_error_has_no_context() {
+        let err = Error::WrongPassword;
+        assert!(matches!(err.root(), Error::WrongPassword));
+        assert_eq!(err.index(), None);
+        assert_eq!(err.path(), None);
+        assert_eq!(err.offset(), None);
+    }
+
+    #[test]
+    fn display_includes_context() {
+        let err = Error::ChecksumMismatch.at_index(3).at_path("a.txt");
+        assert_eq!(err.to_string(), "Checksum mismatch (item 3) in 'a.txt'");
+    }
+
+    #[test]
+    fn source_is_kept() {
+        let err = Error::InvalidFormat("bad header".into())
+            .with_source(std::io::Error::other("short read"));
+        let source = std::error::Error::source(&err).expect("source");
+        assert_eq!(source.to_string(), "short read");
+    }
+
+    #[test]
+    fn io_errors_convert() {
+        let eof = Error::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert!(matches!(eof.root(), Error::UnexpectedEof));
+
+        let other = Error::from(std::io::Error::other("disk full"));
+        assert!(matches!(other.root(), Error::Io(_)));
+
+        // An Error passed through an io::Error comes back unchanged
+        let wrapped = std::io::Error::other(Error::Cancelled.at_index(5));
+        let back = Error::from(wrapped);
+        assert!(matches!(back.root(), Error::Cancelled));
+        assert_eq!(back.index(), Some(5));
+    }
+
+    #[test]
+    fn io_constructor() {
+        let err = Error::io("read failed");
+        assert!(matches!(&err, Error::Io(io) if io.to_string() == "read failed"));
+        assert_eq!(err.to_string(), "I/O error: read failed");
+    }
+}
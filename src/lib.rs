@@ -17,11 +17,13 @@
 //! impl ArchiveFormat for MyFormat {
 //!     fn name() -> &'static str { "MyFormat" }
 //!     fn extension() -> &'static str { "myf" }
-//!     fn class_id() -> [u8; 16] { /* unique GUID bytes */ }
+//!     const CLASS_ID: [u8; 16] = [/* unique GUID bytes */];
 //! }
 //!
 //! impl ArchiveReader for MyFormat {
-//!     fn open(&mut self, data: &[u8]) -> Result<()> { /* ... */ }
+//!     fn open(&mut self, reader: &mut dyn ReadSeek, size: u64, ctx: &dyn OpenContext) -> Result<()> {
+//!         /* ... */
+//!     }
 //!     fn item_count(&self) -> usize { self.items.len() }
 //!     fn get_item(&self, index: usize) -> Option<&ArchiveItem> { self.items.get(index) }
 //!     fn extract(&mut self, index: usize) -> Result<Vec<u8>> { /* ... */ }
@@ -30,14 +32,22 @@
 //! sevenzip_plugin::register_format!(MyFormat);
 //! ```
 
+// Shared by the COM bridge's time conversions and the log's timestamps
+#[cfg(any(windows, test, feature = "testing", feature = "debug"))]
+mod calendar;
+mod checksum;
 mod error;
+pub mod log;
 mod traits;
 mod types;
 
-#[cfg(windows)]
+#[cfg(any(windows, test, feature = "testing"))]
 #[doc(hidden)]
 pub mod windows;
 
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 pub mod prelude {
     //! Re-exports of commonly used types and traits.
     pub use crate::error::*;
@@ -46,8 +56,3 @@ pub mod prelude {
 }
 
 pub use prelude::*;
-
-// Re-export windows types for use by the macro (under different name to avoid conflict)
-#[cfg(windows)]
-#[doc(hidden)]
-pub use ::windows as windows_crate;
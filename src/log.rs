@@ -0,0 +1,333 @@
+//! Diagnostics log shared by the framework and plugin code.
+//!
+//! Logging is compiled in with the `debug` feature; without it every log
+//! macro is a no-op and its arguments are never evaluated. When enabled, the
+//! log is configured from the environment when the first message is written:
+//!
+//! - `SEVENZIP_PLUGIN_LOG` - path of the log file, which is appended to.
+//!   Defaults to the plugin DLL's path with a `.log` extension (e.g.
+//!   `Formats\myformat.log`). Other platforms have no default, so nothing is
+//!   logged unless it is set.
+//! - `SEVENZIP_PLUGIN_LOG_LEVEL` - `error`, `warn`, `info`, `debug` or
+//!   `trace`. Unset or unrecognized values mean `debug`. At `trace`, every
+//!   COM call is logged with its arguments and the HRESULT it returned.
+//!
+//! Plugin code logs through the same file with the level macros:
+//!
+//! ```rust,ignore
+//! sevenzip_plugin::log_warn!("entry {} has an unknown method {}", index, method);
+//! ```
+
+use std::fmt;
+
+/// Severity of a log message, from most to least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Something failed.
+    Error,
+    /// Something looks wrong but the operation continues.
+    Warn,
+    /// High-level progress, such as archives being opened.
+    Info,
+    /// Details useful when debugging a plugin.
+    Debug,
+    /// Every call 7-Zip makes into the plugin.
+    Trace,
+}
+
+impl Level {
+    /// Upper-case name of the level, as written to the log.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// Whether messages at `level` are written anywhere.
+///
+/// Use this to skip expensive work that only feeds the log.
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    #[cfg(feature = "debug")]
+    {
+        sink::logger().is_some_and(|logger| logger.enabled(level))
+    }
+
+    #[cfg(not(feature = "debug"))]
+    {
+        let _ = level;
+        false
+    }
+}
+
+/// Write a message at `level`, if that level is enabled.
+///
+/// Usually called through [`log!`](crate::log!) or the level macros.
+pub fn write(level: Level, args: fmt::Arguments<'_>) {
+    #[cfg(feature = "debug")]
+    if let Some(logger) = sink::logger() {
+        logger.write(level, args);
+    }
+
+    #[cfg(not(feature = "debug"))]
+    let _ = (level, args);
+}
+
+/// Log a message at the given [`Level`].
+///
+/// The arguments are only evaluated if the level is enabled.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if $crate::log::enabled(level) {
+            $crate::log::write(level, format_args!($($arg)*));
+        }
+    }};
+}
+
+/// Log a message at [`Level::Error`].
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Error, $($arg)*) };
+}
+
+/// Log a message at [`Level::Warn`].
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Warn, $($arg)*) };
+}
+
+/// Log a message at [`Level::Info`].
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Info, $($arg)*) };
+}
+
+/// Log a message at [`Level::Debug`].
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Debug, $($arg)*) };
+}
+
+/// Log a message at [`Level::Trace`].
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => { $crate::log!($crate::log::Level::Trace, $($arg)*) };
+}
+
+#[cfg(feature = "debug")]
+mod sink {
+    use std::fmt::{self, Write as _};
+    use std::fs::{File, OpenOptions};
+    use std::io::Write as _;
+    use std::path::PathBuf;
+    use std::sync::{Mutex, OnceLock, PoisonError};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::Level;
+    use crate::calendar::civil_from_days;
+
+    const PATH_VAR: &str = "SEVENZIP_PLUGIN_LOG";
+    const LEVEL_VAR: &str = "SEVENZIP_PLUGIN_LOG_LEVEL";
+
+    pub(super) struct Logger {
+        level: Level,
+        file: Mutex<File>,
+    }
+
+    /// The configured logger, or `None` if the log file can't be opened.
+    pub(super) fn logger() -> Option<&'static Logger> {
+        static LOGGER: OnceLock<Option<Logger>> = OnceLock::new();
+        LOGGER.get_or_init(Logger::from_env).as_ref()
+    }
+
+    impl Logger {
+        fn from_env() -> Option<Self> {
+            let path = std::env::var_os(PATH_VAR)
+                .map(PathBuf::from)
+                .or_else(default_path)?;
+            let level = configured_level(std::env::var(LEVEL_VAR).ok().as_deref());
+            Self::open(path, level)
+        }
+
+        fn open(path: PathBuf, level: Level) -> Option<Self> {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .ok()?;
+
+            Some(Self {
+                level,
+                file: Mutex::new(file),
+            })
+        }
+
+        /// Whether messages at `level` pass the configured level.
+        pub(super) fn enabled(&self, level: Level) -> bool {
+            level <= self.level
+        }
+
+        /// Append a line for the message, unless `level` is filtered out.
+        pub(super) fn write(&self, level: Level, args: fmt::Arguments<'_>) {
+            if !self.enabled(level) {
+                return;
+            }
+
+            // Format the whole line first so concurrent messages don't interleave
+            let mut line = String::new();
+            let _ = writeln!(
+                line,
+                "{} {:?} {:<5} {}",
+                timestamp(),
+                std::thread::current().id(),
+                level,
+                args
+            );
+
+            let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+
+    /// The level named by `SEVENZIP_PLUGIN_LOG_LEVEL`, `Debug` if unset or unrecognized.
+    fn configured_level(value: Option<&str>) -> Level {
+        value.and_then(parse_level).unwrap_or(Level::Debug)
+    }
+
+    fn parse_level(name: &str) -> Option<Level> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    /// `<plugin>.log` next to the plugin DLL.
+    #[cfg(windows)]
+    fn default_path() -> Option<PathBuf> {
+        crate::windows::module_path().map(|path| path.with_extension("log"))
+    }
+
+    #[cfg(not(windows))]
+    fn default_path() -> Option<PathBuf> {
+        None
+    }
+
+    /// Current UTC time as `YYYY-MM-DD HH:MM:SS.mmm`.
+    fn timestamp() -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        format_timestamp(now)
+    }
+
+    /// Format `since_epoch`, the time since the Unix epoch, as `YYYY-MM-DD HH:MM:SS.mmm`.
+    fn format_timestamp(since_epoch: Duration) -> String {
+        let secs = since_epoch.as_secs();
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let secs_of_day = secs % 86_400;
+
+        format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60,
+            since_epoch.subsec_millis()
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn level_names_are_parsed() {
+            assert_eq!(parse_level("error"), Some(Level::Error));
+            assert_eq!(parse_level("Warning"), Some(Level::Warn));
+            assert_eq!(parse_level(" TRACE\n"), Some(Level::Trace));
+            assert_eq!(parse_level("verbose"), None);
+        }
+
+        #[test]
+        fn level_defaults_to_debug() {
+            assert_eq!(configured_level(Some("info")), Level::Info);
+            assert_eq!(configured_level(Some("loud")), Level::Debug);
+            assert_eq!(configured_level(None), Level::Debug);
+        }
+
+        #[test]
+        fn messages_below_the_level_are_dropped() {
+            let path = std::env::temp_dir().join(format!(
+                "sevenzip-plugin-log-test-{}.log",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            let logger = Logger::open(path.clone(), Level::Warn).expect("open log");
+
+            assert!(logger.enabled(Level::Error));
+            assert!(logger.enabled(Level::Warn));
+            assert!(!logger.enabled(Level::Info));
+
+            logger.write(Level::Error, format_args!("kept error"));
+            logger.write(Level::Info, format_args!("dropped info"));
+            logger.write(Level::Warn, format_args!("kept warning"));
+            drop(logger);
+
+            let log = std::fs::read_to_string(&path).expect("read log");
+            let _ = std::fs::remove_file(&path);
+            let lines: Vec<&str> = log.lines().collect();
+            assert_eq!(lines.len(), 2, "{log}");
+            assert!(lines[0].ends_with(" ERROR kept error"), "{log}");
+            assert!(lines[1].ends_with(" WARN  kept warning"), "{log}");
+        }
+
+        #[test]
+        fn timestamps_are_utc_with_milliseconds() {
+            assert_eq!(format_timestamp(Duration::ZERO), "1970-01-01 00:00:00.000");
+            // 2024-02-29 13:05:09.042 UTC
+            assert_eq!(
+                format_timestamp(Duration::from_millis(1_709_211_909_042)),
+                "2024-02-29 13:05:09.042"
+            );
+            assert_eq!(timestamp().len(), "YYYY-MM-DD HH:MM:SS.mmm".len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_run_from_most_to_least_severe() {
+        assert!(Level::Error < Level::Warn);
+        assert!(Level::Debug < Level::Trace);
+        assert_eq!(format!("{:<5}|", Level::Warn), "WARN |");
+    }
+
+    #[cfg(not(feature = "debug"))]
+    #[test]
+    fn nothing_is_enabled_without_the_debug_feature() {
+        assert!(!enabled(Level::Error));
+        assert!(!enabled(Level::Trace));
+    }
+}
@@ -0,0 +1,594 @@
+//! In-process 7-Zip host for testing plugins on any OS.
+//!
+//! [`TestHost`] plays 7-Zip's part: it creates the handler through
+//! `CreateObject` and calls the same COM methods 7-Zip does, passing streams
+//! and callbacks implemented in Rust. Tests therefore cover the whole COM
+//! bridge, not just the plugin's traits. [`TestPlugin`] calls the DLL
+//! exports generated by `register_formats!`, as 7-Zip does when loading the
+//! plugin.
+//!
+//! Requires the `testing` feature; enable it in the plugin's
+//! `[dev-dependencies]` so release builds don't include the host.
+//!
+//! ```rust,ignore
+//! use sevenzip_plugin::prelude::*;
+//! use sevenzip_plugin::testing::{TestHost, UpdateEntry};
+//!
+//! let mut host = TestHost::<MyFormat>::updatable();
+//! let archive = host.update(vec![UpdateEntry::file("a.txt", b"hello".to_vec())])?;
+//!
+//! host.open(archive)?;
+//! assert_eq!(host.item_count()?, 1);
+//! assert_eq!(host.property(0, PropId::PATH)?, Some(PropValue::String("a.txt".into())));
+//!
+//! let report = host.extract_all();
+//! assert!(report.result.is_ok());
+//! assert_eq!(report.data(0), Some(&b"hello"[..]));
+//! ```
+
+mod objects;
+mod plugin;
+
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+
+use crate::traits::{ArchiveReader, ArchiveUpdater};
+use crate::types::{PropId, PropValue};
+use crate::windows::com::{
+    IID_IINARCHIVE, IID_IOUTARCHIVE, IInArchive, IInArchiveVTable, IOutArchive, IOutArchiveVTable,
+};
+use crate::windows::handler::{
+    FormatRegistration, PluginHandler, RegisteredFormat, create_in_vtable, create_out_vtable,
+    create_out_vtable_stub,
+};
+use crate::windows::propvariant::RawPropVariant;
+use crate::windows::sys::BSTR;
+
+pub use crate::windows::com::HandlerPropId;
+pub use crate::windows::sys::{
+    CLASS_E_CLASSNOTAVAILABLE, E_ABORT, E_FAIL, E_INVALIDARG, E_NOINTERFACE, E_NOTIMPL,
+    E_OUTOFMEMORY, E_POINTER, HRESULT, S_FALSE, S_OK,
+};
+pub use plugin::{
+    CreateObjectFn, GetHandlerProperty2Fn, GetIsArcFn, GetNumberOfFormatsFn, TestPlugin,
+};
+
+use objects::{
+    ComPtr, ExtractCallback, InStream, OpenCallback, OutStream, Password, Progress, UpdateCallback,
+};
+
+/// 7-Zip's `maxCheckStartPosition` when opening an archive.
+const MAX_CHECK_START_POSITION: u64 = 1 << 22;
+
+/// Vtables for format `T`, in static memory like the ones `register_format!` emits.
+struct Vtables<T>(PhantomData<T>);
+
+impl<T: ArchiveReader> Vtables<T> {
+    const IN: &'static IInArchiveVTable<PluginHandler<T>> = &create_in_vtable::<T>();
+    const READ_ONLY: &'static IOutArchiveVTable<PluginHandler<T>> = &create_out_vtable_stub::<T>();
+}
+
+impl<T: ArchiveReader + ArchiveUpdater> Vtables<T> {
+    const UPDATABLE: &'static IOutArchiveVTable<PluginHandler<T>> = &create_out_vtable::<T>();
+}
+
+/// 7-Zip's `NArchive::NExtract::NOperationResult`, reported for each item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationResult {
+    Ok,
+    UnsupportedMethod,
+    DataError,
+    CrcError,
+    Unavailable,
+    UnexpectedEnd,
+    DataAfterEnd,
+    IsNotArc,
+    HeadersError,
+    WrongPassword,
+    /// A value 7-Zip doesn't define.
+    Other(i32),
+}
+
+impl OperationResult {
+    fn from_raw(value: i32) -> Self {
+        match value {
+            0 => Self::Ok,
+            1 => Self::UnsupportedMethod,
+            2 => Self::DataError,
+            3 => Self::CrcError,
+            4 => Self::Unavailable,
+            5 => Self::UnexpectedEnd,
+            6 => Self::DataAfterEnd,
+            7 => Self::IsNotArc,
+            8 => Self::HeadersError,
+            9 => Self::WrongPassword,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// An item the plugin opened during `Extract`.
+#[derive(Debug, Clone)]
+pub struct ExtractedItem {
+    /// Index of the item in the archive.
+    pub index: u32,
+    /// The item's path, read from the archive inside `GetStream`.
+    pub path: Option<String>,
+    /// Data written to the output stream (empty when testing).
+    pub data: Vec<u8>,
+    /// Operation result, `None` if the plugin never reported one.
+    pub result: Option<OperationResult>,
+}
+
+/// Everything 7-Zip saw during one `Extract` call.
+#[derive(Debug, Clone)]
+pub struct ExtractReport {
+    /// HRESULT returned by `Extract`.
+    pub result: HRESULT,
+    /// Items in the order the plugin opened them.
+    pub items: Vec<ExtractedItem>,
+    /// Value passed to `SetTotal`.
+    pub total: Option<u64>,
+    /// Last value passed to `SetCompleted`.
+    pub completed: Option<u64>,
+    /// Callback calls made out of the order 7-Zip expects.
+    pub violations: Vec<String>,
+}
+
+impl ExtractReport {
+    /// The last time item `index` was opened.
+    pub fn item(&self, index: u32) -> Option<&ExtractedItem> {
+        self.items.iter().rev().find(|item| item.index == index)
+    }
+
+    /// Data extracted for item `index`.
+    pub fn data(&self, index: u32) -> Option<&[u8]> {
+        self.item(index).map(|item| item.data.as_slice())
+    }
+
+    /// Operation result reported for item `index`.
+    pub fn result_of(&self, index: u32) -> Option<OperationResult> {
+        self.item(index).and_then(|item| item.result)
+    }
+}
+
+/// Progress the plugin reported while opening an archive.
+///
+/// A count stays as it was when the plugin passes null for it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpenProgress {
+    /// Files passed to `SetTotal`.
+    pub total_files: Option<u64>,
+    /// Bytes passed to `SetTotal`.
+    pub total_bytes: Option<u64>,
+    /// Files passed to `SetCompleted`.
+    pub completed_files: Option<u64>,
+    /// Bytes passed to `SetCompleted`.
+    pub completed_bytes: Option<u64>,
+    /// Number of `SetTotal` and `SetCompleted` calls, including bare polls.
+    pub calls: u32,
+}
+
+/// A new file or directory to add in an update.
+#[derive(Debug, Clone, Default)]
+pub struct NewEntry {
+    /// Path inside the archive.
+    pub name: String,
+    /// File contents; ignored for directories.
+    pub data: Vec<u8>,
+    pub is_dir: bool,
+    pub modified: Option<SystemTime>,
+    pub attributes: Option<u32>,
+}
+
+/// One item of the archive produced by [`TestHost::update`].
+#[derive(Debug, Clone)]
+pub enum UpdateEntry {
+    /// Keep item `index` of the open archive, optionally under a new name.
+    Keep { index: u32, rename: Option<String> },
+    /// Add a new item.
+    Add(NewEntry),
+}
+
+impl UpdateEntry {
+    /// Keep an existing item unchanged.
+    pub fn keep(index: u32) -> Self {
+        Self::Keep {
+            index,
+            rename: None,
+        }
+    }
+
+    /// Keep an existing item under a new name.
+    pub fn rename(index: u32, name: impl Into<String>) -> Self {
+        Self::Keep {
+            index,
+            rename: Some(name.into()),
+        }
+    }
+
+    /// Add a file.
+    pub fn file(name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self::Add(NewEntry {
+            name: name.into(),
+            data: data.into(),
+            ..Default::default()
+        })
+    }
+
+    /// Add a directory.
+    pub fn dir(name: impl Into<String>) -> Self {
+        Self::Add(NewEntry {
+            name: name.into(),
+            is_dir: true,
+            ..Default::default()
+        })
+    }
+}
+
+/// A property as 7-Zip sees it through `GetPropertyInfo`/`GetArchivePropertyInfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportedProperty {
+    /// Column name, for properties 7-Zip doesn't know.
+    pub name: Option<String>,
+    pub id: PropId,
+    /// VARTYPE of the values (`VT_BSTR` for strings and bytes).
+    pub var_type: u16,
+}
+
+/// Drives a plugin handler the way 7-Zip does.
+///
+/// The host answers password prompts with the password set by
+/// [`set_password`](Self::set_password); without one, it cancels them like a
+/// user pressing Cancel, and creates unencrypted archives. Progress
+/// callbacks can cancel too, see [`cancel_after`](Self::cancel_after).
+pub struct TestHost<T: ArchiveReader> {
+    /// The handler's IInArchive interface
+    archive: *mut c_void,
+    /// Stream of the open archive
+    stream: Option<ComPtr<InStream>>,
+    password: Password,
+    /// Progress calls answered before cancelling, per operation
+    cancel_after: Option<u32>,
+    /// Progress reported during the last open
+    open_progress: OpenProgress,
+    /// Operation results reported during the last update
+    update_results: Vec<OperationResult>,
+    _format: PhantomData<T>,
+}
+
+impl<T: ArchiveReader> TestHost<T> {
+    /// Create a handler for a read-only format.
+    pub fn new() -> Self {
+        Self::create(RegisteredFormat::new(
+            Vtables::<T>::IN,
+            Vtables::<T>::READ_ONLY,
+        ))
+    }
+
+    /// Create the handler through `CreateObject`, as 7-Zip does.
+    fn create(format: RegisteredFormat<T>) -> Self {
+        let mut archive = std::ptr::null_mut();
+        let formats: [&dyn FormatRegistration; 1] = [&format];
+        let hr = unsafe {
+            crate::windows::exports::create_object(
+                &format.class_id(),
+                &IID_IINARCHIVE,
+                &mut archive,
+                &formats,
+            )
+        };
+        assert!(
+            hr.is_ok() && !archive.is_null(),
+            "CreateObject failed: {:?}",
+            hr
+        );
+
+        Self::from_archive(archive)
+    }
+
+    /// Take over `archive`, an `IInArchive` pointer returned by `CreateObject`.
+    fn from_archive(archive: *mut c_void) -> Self {
+        Self {
+            archive,
+            stream: None,
+            password: Password {
+                password: None,
+                requests: Arc::new(AtomicU32::new(0)),
+            },
+            cancel_after: None,
+            open_progress: OpenProgress::default(),
+            update_results: Vec::new(),
+            _format: PhantomData,
+        }
+    }
+
+    fn in_archive(&self) -> &IInArchive<c_void> {
+        unsafe { IInArchive::<c_void>::from_ptr_mut(self.archive) }
+    }
+
+    fn in_archive_mut(&mut self) -> &mut IInArchive<c_void> {
+        unsafe { IInArchive::<c_void>::from_ptr_mut(self.archive) }
+    }
+
+    /// Set the password given when the plugin asks for one.
+    pub fn set_password(&mut self, password: Option<&str>) {
+        self.password.password = password.map(str::to_string);
+    }
+
+    /// How many times the plugin asked for a password.
+    pub fn password_requests(&self) -> u32 {
+        self.password.requests.load(Ordering::SeqCst)
+    }
+
+    /// Cancel open, extract and update after `progress_calls` calls to the
+    /// callback's `SetTotal`/`SetCompleted`, like a user pressing Cancel.
+    ///
+    /// `None`, the default, never cancels.
+    pub fn cancel_after(&mut self, progress_calls: Option<u32>) {
+        self.cancel_after = progress_calls;
+    }
+
+    /// Progress the plugin reported during the last `open()`.
+    pub fn open_progress(&self) -> &OpenProgress {
+        &self.open_progress
+    }
+
+    /// Operation results the plugin reported during the last `update()`, in order.
+    pub fn update_results(&self) -> &[OperationResult] {
+        &self.update_results
+    }
+
+    /// Open `data` as an archive.
+    ///
+    /// Fails with `S_FALSE` if the plugin doesn't recognize it.
+    pub fn open(&mut self, data: impl Into<Vec<u8>>) -> Result<(), HRESULT> {
+        let stream = ComPtr::new(InStream::new(data.into().into()));
+        let callback = ComPtr::new(OpenCallback::new(
+            self.password.clone(),
+            Progress::new(self.cancel_after),
+        ));
+
+        let hr = unsafe {
+            self.in_archive_mut().open(
+                stream.as_raw(),
+                &MAX_CHECK_START_POSITION,
+                callback.as_raw(),
+            )
+        };
+        self.open_progress =
+            std::mem::take(&mut *callback.log.lock().unwrap_or_else(PoisonError::into_inner));
+        if hr != S_OK {
+            return Err(hr);
+        }
+
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Close the archive.
+    pub fn close(&mut self) -> Result<(), HRESULT> {
+        let hr = unsafe { self.in_archive_mut().close() };
+        self.stream = None;
+        hr.ok().map_err(|_| hr)
+    }
+
+    /// Number of items in the archive.
+    pub fn item_count(&self) -> Result<u32, HRESULT> {
+        let mut count = 0;
+        let hr = unsafe { self.in_archive().get_number_of_items(&mut count) };
+        hr.ok().map(|_| count).map_err(|_| hr)
+    }
+
+    /// Property `id` of item `index`, `None` if the plugin reports it as empty.
+    pub fn property(&self, index: u32, id: PropId) -> Result<Option<PropValue>, HRESULT> {
+        read_property(|value| unsafe { self.in_archive().get_property(index, id.0, value) })
+    }
+
+    /// Archive-level property `id`.
+    pub fn archive_property(&self, id: PropId) -> Result<Option<PropValue>, HRESULT> {
+        read_property(|value| unsafe { self.in_archive().get_archive_property(id.0, value) })
+    }
+
+    /// Item property columns, in the order 7-Zip shows them.
+    pub fn properties(&self) -> Result<Vec<ReportedProperty>, HRESULT> {
+        let archive = self.in_archive();
+        let mut count = 0;
+        let hr = unsafe { archive.get_number_of_properties(&mut count) };
+        hr.ok().map_err(|_| hr)?;
+
+        (0..count)
+            .map(|index| {
+                read_property_info(|name, id, var_type| unsafe {
+                    archive.get_property_info(index, name, id, var_type)
+                })
+            })
+            .collect()
+    }
+
+    /// Archive property rows, in the order 7-Zip shows them.
+    pub fn archive_properties(&self) -> Result<Vec<ReportedProperty>, HRESULT> {
+        let archive = self.in_archive();
+        let mut count = 0;
+        let hr = unsafe { archive.get_number_of_archive_properties(&mut count) };
+        hr.ok().map_err(|_| hr)?;
+
+        (0..count)
+            .map(|index| {
+                read_property_info(|name, id, var_type| unsafe {
+                    archive.get_archive_property_info(index, name, id, var_type)
+                })
+            })
+            .collect()
+    }
+
+    /// Extract items `indices`.
+    pub fn extract(&mut self, indices: &[u32]) -> ExtractReport {
+        self.run_extract(Some(indices), false)
+    }
+
+    /// Extract every item, as 7-Zip does when no selection is given.
+    pub fn extract_all(&mut self) -> ExtractReport {
+        self.run_extract(None, false)
+    }
+
+    /// Test items `indices`.
+    pub fn test(&mut self, indices: &[u32]) -> ExtractReport {
+        self.run_extract(Some(indices), true)
+    }
+
+    /// Test every item.
+    pub fn test_all(&mut self) -> ExtractReport {
+        self.run_extract(None, true)
+    }
+
+    fn run_extract(&mut self, indices: Option<&[u32]>, test: bool) -> ExtractReport {
+        let callback = ComPtr::new(ExtractCallback::new(
+            self.archive,
+            self.password.clone(),
+            Progress::new(self.cancel_after),
+        ));
+        let (ptr, count) = match indices {
+            Some(indices) => (indices.as_ptr(), indices.len() as u32),
+            None => (std::ptr::null(), u32::MAX),
+        };
+
+        let result = unsafe {
+            self.in_archive_mut()
+                .extract(ptr, count, i32::from(test), callback.as_raw())
+        };
+
+        let mut log =
+            std::mem::take(&mut *callback.log.lock().unwrap_or_else(PoisonError::into_inner));
+        let items = log
+            .items
+            .drain(..)
+            .map(|(mut item, data)| {
+                item.data =
+                    std::mem::take(&mut *data.lock().unwrap_or_else(PoisonError::into_inner));
+                item
+            })
+            .collect();
+
+        ExtractReport {
+            result,
+            items,
+            total: log.total,
+            completed: log.completed,
+            violations: log.violations,
+        }
+    }
+}
+
+impl<T: ArchiveReader + ArchiveUpdater> TestHost<T> {
+    /// Create a handler for a format that supports writing.
+    pub fn updatable() -> Self {
+        Self::create(RegisteredFormat::new(
+            Vtables::<T>::IN,
+            Vtables::<T>::UPDATABLE,
+        ))
+    }
+
+    /// Write a new archive made of `entries` through `IOutArchive::UpdateItems`.
+    ///
+    /// `Keep` entries refer to the open archive; with none open, a new archive
+    /// is created. Like 7-Zip, the handler closes the archive afterwards - open
+    /// the returned data to inspect the result.
+    pub fn update(&mut self, entries: Vec<UpdateEntry>) -> Result<Vec<u8>, HRESULT> {
+        let mut out_archive: *mut c_void = std::ptr::null_mut();
+        let hr = unsafe {
+            self.in_archive()
+                .query_interface(&IID_IOUTARCHIVE, &mut out_archive)
+        };
+        if hr.is_err() {
+            return Err(hr);
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let out_stream = ComPtr::new(OutStream::new(Arc::clone(&output)));
+        let count = entries.len() as u32;
+        let callback = ComPtr::new(UpdateCallback::new(
+            self.archive,
+            entries,
+            self.password.clone(),
+            Progress::new(self.cancel_after),
+        ));
+
+        let hr = unsafe {
+            let out = IOutArchive::<c_void>::from_ptr_mut(out_archive);
+            let mut time_type = 0;
+            let _ = out.get_file_time_type(&mut time_type);
+            let hr = out.update_items(out_stream.as_raw(), count, callback.as_raw());
+            out.release();
+            hr
+        };
+        self.stream = None;
+        self.update_results = std::mem::take(
+            &mut callback
+                .log
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .results,
+        );
+
+        if hr != S_OK {
+            return Err(hr);
+        }
+        drop(out_stream);
+        let data = std::mem::take(&mut *output.lock().unwrap_or_else(PoisonError::into_inner));
+        Ok(data)
+    }
+}
+
+impl<T: ArchiveReader> Default for TestHost<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ArchiveReader> Drop for TestHost<T> {
+    fn drop(&mut self) {
+        let open = self.stream.take().is_some();
+        let archive = self.in_archive_mut();
+        unsafe {
+            if open {
+                let _ = archive.close();
+            }
+            archive.release();
+        }
+    }
+}
+
+/// Call a `GetProperty`-style method and read the value it wrote.
+fn read_property(get: impl FnOnce(*mut c_void) -> HRESULT) -> Result<Option<PropValue>, HRESULT> {
+    let mut value = RawPropVariant::default();
+    let hr = get(&mut value as *mut RawPropVariant as *mut c_void);
+    let result = unsafe { value.get_value() };
+    // The caller owns the value, as in 7-Zip
+    unsafe { value.clear() };
+    hr.ok().map(|_| result).map_err(|_| hr)
+}
+
+/// Call a `GetPropertyInfo`-style method and collect its outputs.
+fn read_property_info(
+    get: impl FnOnce(*mut c_void, *mut u32, *mut u32) -> HRESULT,
+) -> Result<ReportedProperty, HRESULT> {
+    let mut name = BSTR::default();
+    let mut id = 0u32;
+    let mut var_type = 0u32;
+    let hr = get(
+        &mut name as *mut BSTR as *mut c_void,
+        &mut id,
+        &mut var_type,
+    );
+    hr.ok().map_err(|_| hr)?;
+
+    Ok(ReportedProperty {
+        name: (!name.is_empty()).then(|| name.to_string()),
+        id: PropId(id),
+        var_type: var_type as u16,
+    })
+}
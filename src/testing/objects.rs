@@ -0,0 +1,875 @@
+//! COM objects the test host hands to the plugin: streams and callbacks.
+//!
+//! Each object starts with its vtable pointer, like the ones 7-Zip passes,
+//! and is freed by its last `Release`.
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use cppvtable::IUnknownVTable;
+
+use super::{ExtractedItem, OpenProgress, OperationResult, UpdateEntry};
+use crate::types::{PropId, PropValue};
+use crate::windows::com::{
+    GUID, IArchiveExtractCallbackVTable, IArchiveOpenCallbackVTable, IArchiveUpdateCallbackVTable,
+    ICryptoGetTextPassword2VTable, ICryptoGetTextPasswordVTable, IID_IARCHIVEEXTRACTCALLBACK,
+    IID_IARCHIVEOPENCALLBACK, IID_IARCHIVEUPDATECALLBACK, IID_ICRYPTOGETTEXTPASSWORD,
+    IID_ICRYPTOGETTEXTPASSWORD2, IID_IINSTREAM, IID_IPROGRESS, IID_ISEQUENTIALINSTREAM,
+    IID_ISEQUENTIALOUTSTREAM, IID_IUNKNOWN, IInArchive, IInStreamVTable,
+    ISequentialOutStreamVTable,
+};
+use crate::windows::propvariant::RawPropVariant;
+use crate::windows::sys::{
+    BSTR, E_ABORT, E_FAIL, E_INVALIDARG, E_NOINTERFACE, E_POINTER, HRESULT, S_OK,
+};
+
+// 7-Zip's NExtract::NAskMode
+const NASK_EXTRACT: i32 = 0;
+
+/// Lock a mutex, ignoring poisoning from a failed test assertion.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// =============================================================================
+// Reference counting
+// =============================================================================
+
+/// A host object exposing one interface through its first field.
+pub(super) trait HostObject: Sized {
+    /// Interfaces answered with the object itself, besides IUnknown.
+    const IIDS: &'static [GUID];
+
+    fn refs(&self) -> &AtomicU32;
+
+    /// A separate object implementing `iid`, e.g. a password callback.
+    fn query_other(&self, _iid: &GUID) -> Option<*mut c_void> {
+        None
+    }
+}
+
+unsafe extern "system" fn query_interface<O: HostObject>(
+    this: *mut O,
+    iid: *const GUID,
+    out: *mut *mut c_void,
+) -> HRESULT {
+    unsafe {
+        if iid.is_null() || out.is_null() {
+            return E_POINTER;
+        }
+
+        let iid = &*iid;
+        if *iid == IID_IUNKNOWN || O::IIDS.contains(iid) {
+            add_ref(this);
+            *out = this.cast();
+            return S_OK;
+        }
+        if let Some(other) = (*this).query_other(iid) {
+            *out = other;
+            return S_OK;
+        }
+
+        *out = std::ptr::null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn add_ref<O: HostObject>(this: *mut O) -> u32 {
+    unsafe { (*this).refs().fetch_add(1, Ordering::SeqCst) + 1 }
+}
+
+unsafe extern "system" fn release<O: HostObject>(this: *mut O) -> u32 {
+    unsafe {
+        let count = (*this).refs().fetch_sub(1, Ordering::SeqCst) - 1;
+        if count == 0 {
+            drop(Box::from_raw(this));
+        }
+        count
+    }
+}
+
+const fn unknown_vtable<O: HostObject>() -> IUnknownVTable<O> {
+    IUnknownVTable {
+        query_interface: query_interface::<O>,
+        add_ref: add_ref::<O>,
+        release: release::<O>,
+    }
+}
+
+/// The host's reference to one of its objects, released when dropped.
+pub(super) struct ComPtr<O: HostObject>(*mut O);
+
+impl<O: HostObject> ComPtr<O> {
+    /// Box `object`, which must have a reference count of 1.
+    pub(super) fn new(object: O) -> Self {
+        Self(Box::into_raw(Box::new(object)))
+    }
+
+    /// The interface pointer to pass to the plugin.
+    pub(super) fn as_raw(&self) -> *mut c_void {
+        self.0.cast()
+    }
+
+    /// A new reference handed over to the plugin.
+    fn into_shared(object: O) -> *mut c_void {
+        Self::new(object).into_raw()
+    }
+
+    fn into_raw(self) -> *mut c_void {
+        let ptr = self.0;
+        std::mem::forget(self);
+        ptr.cast()
+    }
+}
+
+impl<O: HostObject> std::ops::Deref for ComPtr<O> {
+    type Target = O;
+
+    fn deref(&self) -> &O {
+        unsafe { &*self.0 }
+    }
+}
+
+impl<O: HostObject> Drop for ComPtr<O> {
+    fn drop(&mut self) {
+        unsafe { release(self.0) };
+    }
+}
+
+// =============================================================================
+// Streams
+// =============================================================================
+
+/// `IInStream` over a byte buffer.
+#[repr(C)]
+pub(super) struct InStream {
+    vtbl: *const IInStreamVTable<InStream>,
+    refs: AtomicU32,
+    data: Arc<[u8]>,
+    position: Mutex<u64>,
+}
+
+static IN_STREAM_VTBL: IInStreamVTable<InStream> = IInStreamVTable {
+    base: unknown_vtable::<InStream>(),
+    read: in_stream_read,
+    seek: in_stream_seek,
+};
+
+impl InStream {
+    pub(super) fn new(data: Arc<[u8]>) -> Self {
+        Self {
+            vtbl: &IN_STREAM_VTBL,
+            refs: AtomicU32::new(1),
+            data,
+            position: Mutex::new(0),
+        }
+    }
+}
+
+impl HostObject for InStream {
+    const IIDS: &'static [GUID] = &[IID_ISEQUENTIALINSTREAM, IID_IINSTREAM];
+
+    fn refs(&self) -> &AtomicU32 {
+        &self.refs
+    }
+}
+
+unsafe extern "system" fn in_stream_read(
+    this: *mut InStream,
+    data: *mut u8,
+    size: u32,
+    processed_size: *mut u32,
+) -> HRESULT {
+    unsafe {
+        let stream = &*this;
+        let mut position = lock(&stream.position);
+        let start = (*position).min(stream.data.len() as u64) as usize;
+        let len = (size as usize).min(stream.data.len() - start);
+
+        if len > 0 {
+            std::ptr::copy_nonoverlapping(stream.data[start..].as_ptr(), data, len);
+        }
+        *position = (start + len) as u64;
+        if !processed_size.is_null() {
+            *processed_size = len as u32;
+        }
+        S_OK
+    }
+}
+
+unsafe extern "system" fn in_stream_seek(
+    this: *mut InStream,
+    offset: i64,
+    seek_origin: u32,
+    new_position: *mut u64,
+) -> HRESULT {
+    unsafe {
+        let stream = &*this;
+        let mut position = lock(&stream.position);
+        let base = match seek_origin {
+            0 => 0,
+            1 => *position,
+            2 => stream.data.len() as u64,
+            _ => return E_INVALIDARG,
+        };
+        let Some(target) = base.checked_add_signed(offset) else {
+            return E_INVALIDARG;
+        };
+
+        *position = target;
+        if !new_position.is_null() {
+            *new_position = target;
+        }
+        S_OK
+    }
+}
+
+/// `ISequentialOutStream` appending to a shared buffer.
+#[repr(C)]
+pub(super) struct OutStream {
+    vtbl: *const ISequentialOutStreamVTable<OutStream>,
+    refs: AtomicU32,
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+static OUT_STREAM_VTBL: ISequentialOutStreamVTable<OutStream> = ISequentialOutStreamVTable {
+    base: unknown_vtable::<OutStream>(),
+    write: out_stream_write,
+};
+
+impl OutStream {
+    pub(super) fn new(data: Arc<Mutex<Vec<u8>>>) -> Self {
+        Self {
+            vtbl: &OUT_STREAM_VTBL,
+            refs: AtomicU32::new(1),
+            data,
+        }
+    }
+}
+
+impl HostObject for OutStream {
+    const IIDS: &'static [GUID] = &[IID_ISEQUENTIALOUTSTREAM];
+
+    fn refs(&self) -> &AtomicU32 {
+        &self.refs
+    }
+}
+
+unsafe extern "system" fn out_stream_write(
+    this: *mut OutStream,
+    data: *const u8,
+    size: u32,
+    processed_size: *mut u32,
+) -> HRESULT {
+    unsafe {
+        if size > 0 {
+            let bytes = std::slice::from_raw_parts(data, size as usize);
+            lock(&(*this).data).extend_from_slice(bytes);
+        }
+        if !processed_size.is_null() {
+            *processed_size = size;
+        }
+        S_OK
+    }
+}
+
+// =============================================================================
+// Password callbacks
+// =============================================================================
+
+/// The password the user would type, and how often they were asked.
+#[derive(Clone)]
+pub(super) struct Password {
+    pub(super) password: Option<String>,
+    pub(super) requests: Arc<AtomicU32>,
+}
+
+impl Password {
+    fn request(&self) -> Option<&str> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        self.password.as_deref()
+    }
+}
+
+/// `ICryptoGetTextPassword`: without a password the user cancels the prompt.
+#[repr(C)]
+struct GetPassword {
+    vtbl: *const ICryptoGetTextPasswordVTable<GetPassword>,
+    refs: AtomicU32,
+    password: Password,
+}
+
+static GET_PASSWORD_VTBL: ICryptoGetTextPasswordVTable<GetPassword> =
+    ICryptoGetTextPasswordVTable {
+        base: unknown_vtable::<GetPassword>(),
+        crypto_get_text_password: get_password,
+    };
+
+impl GetPassword {
+    fn shared(password: &Password) -> *mut c_void {
+        ComPtr::into_shared(Self {
+            vtbl: &GET_PASSWORD_VTBL,
+            refs: AtomicU32::new(1),
+            password: password.clone(),
+        })
+    }
+}
+
+impl HostObject for GetPassword {
+    const IIDS: &'static [GUID] = &[IID_ICRYPTOGETTEXTPASSWORD];
+
+    fn refs(&self) -> &AtomicU32 {
+        &self.refs
+    }
+}
+
+unsafe extern "system" fn get_password(this: *mut GetPassword, password: *mut *mut u16) -> HRESULT {
+    unsafe {
+        if password.is_null() {
+            return E_POINTER;
+        }
+        match (*this).password.request() {
+            Some(value) => {
+                *password = BSTR::from(value).into_raw() as *mut u16;
+                S_OK
+            }
+            None => E_ABORT,
+        }
+    }
+}
+
+/// `ICryptoGetTextPassword2`: without a password the archive is not encrypted.
+#[repr(C)]
+struct GetPassword2 {
+    vtbl: *const ICryptoGetTextPassword2VTable<GetPassword2>,
+    refs: AtomicU32,
+    password: Password,
+}
+
+static GET_PASSWORD2_VTBL: ICryptoGetTextPassword2VTable<GetPassword2> =
+    ICryptoGetTextPassword2VTable {
+        base: unknown_vtable::<GetPassword2>(),
+        crypto_get_text_password2: get_password2,
+    };
+
+impl GetPassword2 {
+    fn shared(password: &Password) -> *mut c_void {
+        ComPtr::into_shared(Self {
+            vtbl: &GET_PASSWORD2_VTBL,
+            refs: AtomicU32::new(1),
+            password: password.clone(),
+        })
+    }
+}
+
+impl HostObject for GetPassword2 {
+    const IIDS: &'static [GUID] = &[IID_ICRYPTOGETTEXTPASSWORD2];
+
+    fn refs(&self) -> &AtomicU32 {
+        &self.refs
+    }
+}
+
+unsafe extern "system" fn get_password2(
+    this: *mut GetPassword2,
+    password_is_defined: *mut i32,
+    password: *mut *mut u16,
+) -> HRESULT {
+    unsafe {
+        if password_is_defined.is_null() || password.is_null() {
+            return E_POINTER;
+        }
+        match (*this).password.request() {
+            Some(value) => {
+                *password_is_defined = 1;
+                *password = BSTR::from(value).into_raw() as *mut u16;
+            }
+            None => {
+                *password_is_defined = 0;
+                *password = std::ptr::null_mut();
+            }
+        }
+        S_OK
+    }
+}
+
+// =============================================================================
+// Cancellation
+// =============================================================================
+
+/// Counts a callback's progress calls, and cancels once there were too many.
+pub(super) struct Progress {
+    calls: AtomicU32,
+    /// Progress calls answered before the user "presses Cancel"
+    cancel_after: Option<u32>,
+}
+
+impl Progress {
+    pub(super) fn new(cancel_after: Option<u32>) -> Self {
+        Self {
+            calls: AtomicU32::new(0),
+            cancel_after,
+        }
+    }
+
+    /// Answer a `SetTotal`/`SetCompleted` call: E_ABORT once cancelled.
+    fn report(&self) -> HRESULT {
+        let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        match self.cancel_after {
+            Some(limit) if calls > limit => E_ABORT,
+            _ => S_OK,
+        }
+    }
+}
+
+// =============================================================================
+// Open callback
+// =============================================================================
+
+/// `IArchiveOpenCallback` recording progress, also answering password prompts.
+#[repr(C)]
+pub(super) struct OpenCallback {
+    vtbl: *const IArchiveOpenCallbackVTable<OpenCallback>,
+    refs: AtomicU32,
+    password: Password,
+    progress: Progress,
+    pub(super) log: Mutex<OpenProgress>,
+}
+
+static OPEN_CALLBACK_VTBL: IArchiveOpenCallbackVTable<OpenCallback> = IArchiveOpenCallbackVTable {
+    base: unknown_vtable::<OpenCallback>(),
+    set_total: open_set_total,
+    set_completed: open_set_completed,
+};
+
+impl OpenCallback {
+    pub(super) fn new(password: Password, progress: Progress) -> Self {
+        Self {
+            vtbl: &OPEN_CALLBACK_VTBL,
+            refs: AtomicU32::new(1),
+            password,
+            progress,
+            log: Mutex::new(OpenProgress::default()),
+        }
+    }
+}
+
+impl HostObject for OpenCallback {
+    const IIDS: &'static [GUID] = &[IID_IARCHIVEOPENCALLBACK];
+
+    fn refs(&self) -> &AtomicU32 {
+        &self.refs
+    }
+
+    fn query_other(&self, iid: &GUID) -> Option<*mut c_void> {
+        (*iid == IID_ICRYPTOGETTEXTPASSWORD).then(|| GetPassword::shared(&self.password))
+    }
+}
+
+/// Read an optional count; null means "unchanged", as in 7-Zip.
+unsafe fn update_count(count: &mut Option<u64>, value: *const u64) {
+    if !value.is_null() {
+        *count = Some(unsafe { *value });
+    }
+}
+
+unsafe extern "system" fn open_set_total(
+    this: *mut OpenCallback,
+    files: *const u64,
+    bytes: *const u64,
+) -> HRESULT {
+    unsafe {
+        let mut log = lock(&(*this).log);
+        log.calls += 1;
+        update_count(&mut log.total_files, files);
+        update_count(&mut log.total_bytes, bytes);
+        drop(log);
+        (*this).progress.report()
+    }
+}
+
+unsafe extern "system" fn open_set_completed(
+    this: *mut OpenCallback,
+    files: *const u64,
+    bytes: *const u64,
+) -> HRESULT {
+    unsafe {
+        let mut log = lock(&(*this).log);
+        log.calls += 1;
+        update_count(&mut log.completed_files, files);
+        update_count(&mut log.completed_bytes, bytes);
+        drop(log);
+        (*this).progress.report()
+    }
+}
+
+// =============================================================================
+// Extract callback
+// =============================================================================
+
+/// What the extract callback has seen so far.
+#[derive(Default)]
+pub(super) struct ExtractLog {
+    pub(super) total: Option<u64>,
+    pub(super) completed: Option<u64>,
+    /// Items in the order the plugin opened them, with their output buffers
+    pub(super) items: Vec<(ExtractedItem, Arc<Mutex<Vec<u8>>>)>,
+    /// Position in `items` of the item awaiting its operation result
+    current: Option<usize>,
+    /// Calls made out of the order 7-Zip expects
+    pub(super) violations: Vec<String>,
+}
+
+/// `IArchiveExtractCallback` recording every item's output and result.
+///
+/// Like 7-Zip, it reads the item's path from the archive inside `GetStream`.
+#[repr(C)]
+pub(super) struct ExtractCallback {
+    vtbl: *const IArchiveExtractCallbackVTable<ExtractCallback>,
+    refs: AtomicU32,
+    archive: *mut c_void,
+    password: Password,
+    progress: Progress,
+    pub(super) log: Mutex<ExtractLog>,
+}
+
+static EXTRACT_CALLBACK_VTBL: IArchiveExtractCallbackVTable<ExtractCallback> =
+    IArchiveExtractCallbackVTable {
+        base: unknown_vtable::<ExtractCallback>(),
+        set_total: extract_set_total,
+        set_completed: extract_set_completed,
+        get_stream: extract_get_stream,
+        prepare_operation: extract_prepare_operation,
+        set_operation_result: extract_set_operation_result,
+    };
+
+impl ExtractCallback {
+    /// Callback for extracting from `archive`, an `IInArchive` pointer.
+    pub(super) fn new(archive: *mut c_void, password: Password, progress: Progress) -> Self {
+        Self {
+            vtbl: &EXTRACT_CALLBACK_VTBL,
+            refs: AtomicU32::new(1),
+            archive,
+            password,
+            progress,
+            log: Mutex::new(ExtractLog::default()),
+        }
+    }
+}
+
+impl HostObject for ExtractCallback {
+    const IIDS: &'static [GUID] = &[IID_IPROGRESS, IID_IARCHIVEEXTRACTCALLBACK];
+
+    fn refs(&self) -> &AtomicU32 {
+        &self.refs
+    }
+
+    fn query_other(&self, iid: &GUID) -> Option<*mut c_void> {
+        (*iid == IID_ICRYPTOGETTEXTPASSWORD).then(|| GetPassword::shared(&self.password))
+    }
+}
+
+unsafe extern "system" fn extract_set_total(this: *mut ExtractCallback, total: u64) -> HRESULT {
+    unsafe {
+        lock(&(*this).log).total = Some(total);
+        (*this).progress.report()
+    }
+}
+
+unsafe extern "system" fn extract_set_completed(
+    this: *mut ExtractCallback,
+    complete_value: *const u64,
+) -> HRESULT {
+    unsafe {
+        update_count(&mut lock(&(*this).log).completed, complete_value);
+        (*this).progress.report()
+    }
+}
+
+unsafe extern "system" fn extract_get_stream(
+    this: *mut ExtractCallback,
+    index: u32,
+    out_stream: *mut *mut c_void,
+    ask_extract_mode: i32,
+) -> HRESULT {
+    unsafe {
+        if out_stream.is_null() {
+            return E_POINTER;
+        }
+        *out_stream = std::ptr::null_mut();
+
+        let callback = &*this;
+
+        // Call back into the archive while it is extracting, as 7-Zip does
+        let path = archive_property(callback.archive, index, PropId::PATH)
+            .and_then(|value| value.as_str().map(str::to_string));
+
+        let mut log = lock(&callback.log);
+        if let Some(pending) = log.current {
+            let pending = log.items[pending].0.index;
+            log.violations.push(format!(
+                "GetStream({index}) before the result of item {pending}"
+            ));
+        }
+
+        let data = Arc::new(Mutex::new(Vec::new()));
+        if ask_extract_mode == NASK_EXTRACT {
+            *out_stream = ComPtr::into_shared(OutStream::new(Arc::clone(&data)));
+        }
+
+        let item = ExtractedItem {
+            index,
+            path,
+            data: Vec::new(),
+            result: None,
+        };
+        log.current = Some(log.items.len());
+        log.items.push((item, data));
+        S_OK
+    }
+}
+
+unsafe extern "system" fn extract_prepare_operation(
+    this: *mut ExtractCallback,
+    _ask_extract_mode: i32,
+) -> HRESULT {
+    let mut log = unsafe { lock(&(*this).log) };
+    if log.current.is_none() {
+        log.violations
+            .push("PrepareOperation without GetStream".to_string());
+    }
+    S_OK
+}
+
+unsafe extern "system" fn extract_set_operation_result(
+    this: *mut ExtractCallback,
+    result: i32,
+) -> HRESULT {
+    let mut log = unsafe { lock(&(*this).log) };
+    match log.current.take() {
+        Some(current) => log.items[current].0.result = Some(OperationResult::from_raw(result)),
+        None => {
+            log.violations
+                .push(format!("SetOperationResult({result}) without GetStream"));
+            return E_FAIL;
+        }
+    }
+    S_OK
+}
+
+/// Read a property of item `index` through `IInArchive::GetProperty`.
+unsafe fn archive_property(archive: *mut c_void, index: u32, id: PropId) -> Option<PropValue> {
+    unsafe {
+        let mut value = RawPropVariant::default();
+        let hr = IInArchive::<c_void>::from_ptr_mut(archive).get_property(
+            index,
+            id.0,
+            &mut value as *mut RawPropVariant as *mut c_void,
+        );
+        let result = if hr.is_ok() { value.get_value() } else { None };
+        value.clear();
+        result
+    }
+}
+
+// =============================================================================
+// Update callback
+// =============================================================================
+
+/// What the update callback has seen so far.
+#[derive(Default)]
+pub(super) struct UpdateLog {
+    pub(super) total: Option<u64>,
+    pub(super) completed: Option<u64>,
+    pub(super) results: Vec<OperationResult>,
+}
+
+/// `IArchiveUpdateCallback` describing the new archive's items.
+///
+/// Properties of kept items that are not overridden come from the archive
+/// being updated, as 7-Zip reads them.
+#[repr(C)]
+pub(super) struct UpdateCallback {
+    vtbl: *const IArchiveUpdateCallbackVTable<UpdateCallback>,
+    refs: AtomicU32,
+    archive: *mut c_void,
+    entries: Vec<UpdateEntry>,
+    password: Password,
+    progress: Progress,
+    pub(super) log: Mutex<UpdateLog>,
+}
+
+static UPDATE_CALLBACK_VTBL: IArchiveUpdateCallbackVTable<UpdateCallback> =
+    IArchiveUpdateCallbackVTable {
+        base: unknown_vtable::<UpdateCallback>(),
+        set_total: update_set_total,
+        set_completed: update_set_completed,
+        get_update_item_info: update_get_item_info,
+        get_property: update_get_property,
+        get_stream: update_get_stream,
+        set_operation_result: update_set_operation_result,
+    };
+
+impl UpdateCallback {
+    /// Callback producing `entries` from `archive`, an `IInArchive` pointer.
+    pub(super) fn new(
+        archive: *mut c_void,
+        entries: Vec<UpdateEntry>,
+        password: Password,
+        progress: Progress,
+    ) -> Self {
+        Self {
+            vtbl: &UPDATE_CALLBACK_VTBL,
+            refs: AtomicU32::new(1),
+            archive,
+            entries,
+            password,
+            progress,
+            log: Mutex::new(UpdateLog::default()),
+        }
+    }
+}
+
+impl HostObject for UpdateCallback {
+    const IIDS: &'static [GUID] = &[IID_IPROGRESS, IID_IARCHIVEUPDATECALLBACK];
+
+    fn refs(&self) -> &AtomicU32 {
+        &self.refs
+    }
+
+    fn query_other(&self, iid: &GUID) -> Option<*mut c_void> {
+        (*iid == IID_ICRYPTOGETTEXTPASSWORD2).then(|| GetPassword2::shared(&self.password))
+    }
+}
+
+unsafe extern "system" fn update_set_total(this: *mut UpdateCallback, total: u64) -> HRESULT {
+    unsafe {
+        lock(&(*this).log).total = Some(total);
+        (*this).progress.report()
+    }
+}
+
+unsafe extern "system" fn update_set_completed(
+    this: *mut UpdateCallback,
+    complete_value: *const u64,
+) -> HRESULT {
+    unsafe {
+        update_count(&mut lock(&(*this).log).completed, complete_value);
+        (*this).progress.report()
+    }
+}
+
+unsafe extern "system" fn update_get_item_info(
+    this: *mut UpdateCallback,
+    index: u32,
+    new_data: *mut i32,
+    new_props: *mut i32,
+    index_in_archive: *mut u32,
+) -> HRESULT {
+    unsafe {
+        let callback = &*this;
+        let Some(entry) = callback.entries.get(index as usize) else {
+            return E_INVALIDARG;
+        };
+        let (data, props, existing) = match entry {
+            UpdateEntry::Keep { index, rename } => (0, i32::from(rename.is_some()), *index),
+            UpdateEntry::Add(_) => (1, 1, u32::MAX),
+        };
+
+        if !new_data.is_null() {
+            *new_data = data;
+        }
+        if !new_props.is_null() {
+            *new_props = props;
+        }
+        if !index_in_archive.is_null() {
+            *index_in_archive = existing;
+        }
+        S_OK
+    }
+}
+
+unsafe extern "system" fn update_get_property(
+    this: *mut UpdateCallback,
+    index: u32,
+    prop_id: u32,
+    value: *mut c_void,
+) -> HRESULT {
+    unsafe {
+        if value.is_null() {
+            return E_POINTER;
+        }
+        let callback = &*this;
+        let Some(entry) = callback.entries.get(index as usize) else {
+            return E_INVALIDARG;
+        };
+        let prop = &mut *(value as *mut RawPropVariant);
+        prop.set_empty();
+
+        let id = PropId(prop_id);
+        match entry {
+            UpdateEntry::Add(item) => match id {
+                PropId::PATH => prop.set_bstr(&item.name),
+                PropId::IS_DIR => prop.set_bool(item.is_dir),
+                PropId::SIZE => prop.set_u64(item.data.len() as u64),
+                PropId::ATTRIB => {
+                    if let Some(attributes) = item.attributes {
+                        prop.set_u32(attributes);
+                    }
+                }
+                PropId::MTIME => {
+                    if let Some(modified) = item.modified {
+                        prop.set_value(&PropValue::FileTime(modified));
+                    }
+                }
+                _ => {}
+            },
+            UpdateEntry::Keep {
+                rename: Some(name), ..
+            } if id == PropId::PATH => prop.set_bstr(name),
+            UpdateEntry::Keep { index, .. } => {
+                if let Some(existing) = archive_property(callback.archive, *index, id) {
+                    prop.set_value(&existing);
+                }
+            }
+        }
+        S_OK
+    }
+}
+
+unsafe extern "system" fn update_get_stream(
+    this: *mut UpdateCallback,
+    index: u32,
+    in_stream: *mut *mut c_void,
+) -> HRESULT {
+    unsafe {
+        if in_stream.is_null() {
+            return E_POINTER;
+        }
+        *in_stream = std::ptr::null_mut();
+
+        let callback = &*this;
+        match callback.entries.get(index as usize) {
+            Some(UpdateEntry::Add(item)) => {
+                if !item.is_dir {
+                    *in_stream = ComPtr::into_shared(InStream::new(item.data.clone().into()));
+                }
+                S_OK
+            }
+            _ => E_INVALIDARG,
+        }
+    }
+}
+
+unsafe extern "system" fn update_set_operation_result(
+    this: *mut UpdateCallback,
+    operation_result: i32,
+) -> HRESULT {
+    unsafe {
+        lock(&(*this).log)
+            .results
+            .push(OperationResult::from_raw(operation_result));
+    }
+    S_OK
+}
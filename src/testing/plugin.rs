@@ -0,0 +1,146 @@
+//! The DLL exports, called the way 7-Zip calls them when loading a plugin.
+
+use std::ffi::c_void;
+
+use super::TestHost;
+use crate::traits::ArchiveReader;
+use crate::types::{Detection, PropValue};
+use crate::windows::com::{HandlerPropId, IID_IINARCHIVE};
+use crate::windows::exports::{IsArcFunc, guid_from_bytes};
+use crate::windows::propvariant::{RawPropVariant, VT_BSTR};
+use crate::windows::sys::{E_FAIL, GUID, HRESULT, S_OK};
+
+/// `CreateObject`, as generated by `register_formats!`.
+pub type CreateObjectFn =
+    unsafe extern "system" fn(*const GUID, *const GUID, *mut *mut c_void) -> HRESULT;
+
+/// `GetNumberOfFormats`, as generated by `register_formats!`.
+pub type GetNumberOfFormatsFn = unsafe extern "system" fn(*mut u32) -> HRESULT;
+
+/// `GetHandlerProperty2`, as generated by `register_formats!`.
+pub type GetHandlerProperty2Fn = unsafe extern "system" fn(u32, u32, *mut c_void) -> HRESULT;
+
+/// `GetIsArc`, as generated by `register_formats!`.
+pub type GetIsArcFn = unsafe extern "system" fn(u32, *mut Option<IsArcFunc>) -> HRESULT;
+
+/// A plugin DLL, seen through the exports `register_formats!` generates.
+///
+/// ```rust,ignore
+/// sevenzip_plugin::register_formats!(PakFormat: updatable, IdxFormat);
+///
+/// let plugin = TestPlugin::new(CreateObject, GetNumberOfFormats, GetHandlerProperty2, GetIsArc);
+/// assert_eq!(plugin.format_count()?, 2);
+/// let mut host = plugin.host::<IdxFormat>()?;
+/// ```
+pub struct TestPlugin {
+    create_object: CreateObjectFn,
+    get_number_of_formats: GetNumberOfFormatsFn,
+    get_handler_property2: GetHandlerProperty2Fn,
+    get_is_arc: GetIsArcFn,
+}
+
+impl TestPlugin {
+    /// Wrap a plugin's exports.
+    pub fn new(
+        create_object: CreateObjectFn,
+        get_number_of_formats: GetNumberOfFormatsFn,
+        get_handler_property2: GetHandlerProperty2Fn,
+        get_is_arc: GetIsArcFn,
+    ) -> Self {
+        Self {
+            create_object,
+            get_number_of_formats,
+            get_handler_property2,
+            get_is_arc,
+        }
+    }
+
+    /// Number of formats the plugin registers.
+    pub fn format_count(&self) -> Result<u32, HRESULT> {
+        let mut count = 0;
+        let hr = unsafe { (self.get_number_of_formats)(&mut count) };
+        hr.ok().map(|_| count).map_err(|_| hr)
+    }
+
+    /// Handler property `id` of format `format`.
+    ///
+    /// `ClassId`, `Signature` and `MultiSignature` are binary strings and are
+    /// returned as [`PropValue::Bytes`].
+    pub fn handler_property(
+        &self,
+        format: u32,
+        id: HandlerPropId,
+    ) -> Result<Option<PropValue>, HRESULT> {
+        let mut value = RawPropVariant::default();
+        let hr = unsafe {
+            (self.get_handler_property2)(
+                format,
+                id as u32,
+                &mut value as *mut RawPropVariant as *mut c_void,
+            )
+        };
+        let binary = matches!(
+            id,
+            HandlerPropId::ClassId | HandlerPropId::Signature | HandlerPropId::MultiSignature
+        );
+        let result = unsafe {
+            if binary && value.vt == VT_BSTR {
+                Some(PropValue::Bytes(bstr_bytes(value.data as *const u8)))
+            } else {
+                value.get_value()
+            }
+        };
+        // The caller owns the value, as in 7-Zip
+        unsafe { value.clear() };
+        hr.ok().map(|_| result).map_err(|_| hr)
+    }
+
+    /// Run format `format`'s IsArc function on `header`.
+    ///
+    /// `None` if the format has no IsArc function.
+    pub fn is_arc(&self, format: u32, header: &[u8]) -> Result<Option<Detection>, HRESULT> {
+        let mut is_arc = None;
+        let hr = unsafe { (self.get_is_arc)(format, &mut is_arc) };
+        hr.ok().map_err(|_| hr)?;
+
+        let Some(is_arc) = is_arc else {
+            return Ok(None);
+        };
+        match unsafe { is_arc(header.as_ptr(), header.len()) } {
+            0 => Ok(Some(Detection::No)),
+            1 => Ok(Some(Detection::Yes)),
+            2 => Ok(Some(Detection::NeedMoreData)),
+            other => panic!("IsArc returned {}, which 7-Zip doesn't define", other),
+        }
+    }
+
+    /// Create the handler for format `T` through `CreateObject`, by its class ID.
+    ///
+    /// Fails with `CLASS_E_CLASSNOTAVAILABLE` if the plugin doesn't register `T`.
+    pub fn host<T: ArchiveReader>(&self) -> Result<TestHost<T>, HRESULT> {
+        let class_id = guid_from_bytes(&T::CLASS_ID);
+        let mut archive = std::ptr::null_mut();
+        let hr = unsafe { (self.create_object)(&class_id, &IID_IINARCHIVE, &mut archive) };
+        if hr != S_OK {
+            return Err(hr);
+        }
+        if archive.is_null() {
+            return Err(E_FAIL);
+        }
+        Ok(TestHost::from_archive(archive))
+    }
+}
+
+/// Copy the raw bytes of a binary BSTR, using its length prefix.
+///
+/// # Safety
+/// `data` must be null or point to a BSTR's data.
+unsafe fn bstr_bytes(data: *const u8) -> Vec<u8> {
+    if data.is_null() {
+        return Vec::new();
+    }
+    unsafe {
+        let len = data.sub(std::mem::size_of::<u32>()).cast::<u32>().read() as usize;
+        std::slice::from_raw_parts(data, len).to_vec()
+    }
+}
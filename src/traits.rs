@@ -1,8 +1,11 @@
 //! Safe traits that plugin authors implement.
 
+use crate::checksum::Crc32Writer;
 use crate::error::Result;
 use crate::types::{
-    ArchiveItem, PasswordProvider, PasswordRequester, ProgressCallback, UpdateItem,
+    ArchiveDiagnostics, ArchiveItem, ArchiveProperty, Detection, ExtractSinks, FormatExtension,
+    FormatFlags, OpenContext, PasswordProvider, PasswordRequester, ProgressCallback, PropId,
+    PropValue, PropertyInfo, TimePrecision, UpdatePlan,
 };
 use std::io::{Read, Seek, Write};
 
@@ -25,9 +28,30 @@ pub trait ArchiveFormat: Default + Send + 'static {
     /// File extension without the dot (e.g., "era", "zip").
     fn extension() -> &'static str;
 
+    /// All extensions of a format known by several (e.g. "pak", "pk2", "dat"),
+    /// each with an optional add-extension:
+    ///
+    /// ```rust,ignore
+    /// fn extensions() -> &'static [FormatExtension] {
+    ///     const EXTENSIONS: &[FormatExtension] = &[
+    ///         FormatExtension::new("tgzx").with_add_extension("tar"),
+    ///         FormatExtension::new("gzx"),
+    ///     ];
+    ///     EXTENSIONS
+    /// }
+    /// ```
+    ///
+    /// When non-empty, this takes precedence over `extension()`.
+    fn extensions() -> &'static [FormatExtension] {
+        &[]
+    }
+
     /// Unique class ID (GUID) for this format.
     /// Generate with: `[0x12, 0x34, ..., 0xEF]` (16 bytes)
-    fn class_id() -> [u8; 16];
+    ///
+    /// This is a constant so that `register_formats!` can reject duplicate
+    /// class IDs at compile time.
+    const CLASS_ID: [u8; 16];
 
     /// Optional file signature/magic bytes for format detection.
     /// Return `None` if the format cannot be detected by magic bytes
@@ -36,6 +60,81 @@ pub trait ArchiveFormat: Default + Send + 'static {
         None
     }
 
+    /// All signatures of a format that has several magic values
+    /// (e.g. one per historic version). Each may be at most 255 bytes.
+    ///
+    /// When non-empty, this takes precedence over `signature()`.
+    fn signatures() -> &'static [&'static [u8]] {
+        &[]
+    }
+
+    /// Offset of the signature from the start of the file (default 0).
+    fn signature_offset() -> u32 {
+        0
+    }
+
+    /// Capabilities and open behaviour of the format (default: none).
+    ///
+    /// For example a format with a trailing index that stores symlinks:
+    ///
+    /// ```rust,ignore
+    /// fn flags() -> FormatFlags {
+    ///     FormatFlags::BACKWARD_OPEN | FormatFlags::SYMLINKS
+    /// }
+    /// ```
+    fn flags() -> FormatFlags {
+        FormatFlags::NONE
+    }
+
+    /// How precisely the format stores file times (default: Windows FILETIME).
+    ///
+    /// Formats that store DOS or Unix times should say so, otherwise 7-Zip's
+    /// "update changed files" sees every file as modified. Declare which
+    /// times are stored with the `FormatFlags::MTIME`/`CTIME`/`ATIME` flags.
+    fn time_precision() -> TimePrecision {
+        TimePrecision::Windows
+    }
+
+    /// Quickly check whether `header` (the first bytes of a file) starts an
+    /// archive of this format.
+    ///
+    /// 7-Zip calls this before creating a handler, so formats without a
+    /// fixed magic can reject files cheaply instead of being fully opened.
+    /// It must not assume `header` has any particular length.
+    ///
+    /// The default checks the declared signatures at `signature_offset()`,
+    /// and answers `Detection::Yes` for formats without a signature.
+    fn detect(header: &[u8]) -> Detection {
+        let single;
+        let signatures = match (Self::signatures(), Self::signature()) {
+            ([], None) => return Detection::Yes,
+            ([], Some(sig)) => {
+                single = [sig];
+                &single[..]
+            }
+            (sigs, _) => sigs,
+        };
+
+        let available = header
+            .get(Self::signature_offset() as usize..)
+            .unwrap_or_default();
+        let mut need_more = false;
+        for sig in signatures {
+            if available.starts_with(sig) {
+                return Detection::Yes;
+            }
+            if available.len() < sig.len() && sig.starts_with(available) {
+                need_more = true;
+            }
+        }
+
+        if need_more {
+            Detection::NeedMoreData
+        } else {
+            Detection::No
+        }
+    }
+
     /// Whether this format supports creating new archives.
     fn supports_write() -> bool {
         false
@@ -59,15 +158,62 @@ pub trait ArchiveReader: ArchiveFormat {
     ///
     /// - `reader`: A seekable reader for the archive data
     /// - `size`: Total size of the archive in bytes
+    /// - `ctx`: Reports open progress to 7-Zip and tells you if the user cancelled.
+    ///   Formats that scan many index records should call `ctx.set_completed()`
+    ///   periodically and propagate its error.
     ///
     /// Store any parsed metadata internally for later extraction.
-    fn open(&mut self, reader: &mut dyn ReadSeek, size: u64) -> Result<()>;
+    ///
+    /// Return `Error::NotArchive` if the data is not in your format, so 7-Zip
+    /// can try other handlers. Once the signature matches, report damage with
+    /// a specific error (`InvalidFormat`, `UnexpectedEof`, `WrongPassword`,
+    /// `UnsupportedMethod`) and 7-Zip will tell the user what is wrong with the archive.
+    fn open(&mut self, reader: &mut dyn ReadSeek, size: u64, ctx: &dyn OpenContext) -> Result<()>;
 
     /// Returns the number of items in the archive.
     fn item_count(&self) -> usize;
 
     /// Get information about an item by index.
-    fn get_item(&self, index: usize) -> Option<&ArchiveItem>;
+    ///
+    /// Formats that keep a fully built `ArchiveItem` per entry return it here.
+    /// Formats with very large or compact indexes can leave this returning
+    /// `None` and override `item_property()` instead.
+    ///
+    /// A format must do one or the other: items with neither have no
+    /// properties at all, and the framework logs a warning when 7-Zip asks
+    /// for the path of such an item.
+    fn get_item(&self, index: usize) -> Option<&ArchiveItem> {
+        let _ = index;
+        None
+    }
+
+    /// Get one property of an item, e.g. for a column of 7-Zip's file list.
+    ///
+    /// 7-Zip asks for each property separately, so a format can decode
+    /// entries on demand from its own compact index instead of building an
+    /// `ArchiveItem` for each:
+    ///
+    /// ```rust,ignore
+    /// fn item_property(&self, index: usize, id: PropId) -> Option<PropValue> {
+    ///     let entry = self.index.entry(index)?;
+    ///     match id {
+    ///         PropId::PATH => Some(self.names.decode(entry.name_offset).into()),
+    ///         PropId::SIZE => Some(entry.size.into()),
+    ///         PropId::IS_DIR => Some(entry.is_dir().into()),
+    ///         _ => None,
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// The framework reads `PropId::SIZE` for progress and `PropId::CRC` in
+    /// the default `test()`. Return `None` for properties the item doesn't have.
+    ///
+    /// The default implementation reads the item returned by `get_item()`, so
+    /// a format whose `get_item()` returns `None` must override this or its
+    /// items will have no properties at all.
+    fn item_property(&self, index: usize, id: PropId) -> Option<PropValue> {
+        self.get_item(index)?.property_value(id)
+    }
 
     /// Extract an item's data by index.
     ///
@@ -86,9 +232,7 @@ pub trait ArchiveReader: ArchiveFormat {
     fn extract_to(&mut self, index: usize, writer: &mut dyn Write) -> Result<u64> {
         let data = self.extract(index)?;
         let len = data.len() as u64;
-        writer
-            .write_all(&data)
-            .map_err(|e| crate::error::Error::Io(e.to_string()))?;
+        writer.write_all(&data)?;
         Ok(len)
     }
 
@@ -104,6 +248,64 @@ pub trait ArchiveReader: ArchiveFormat {
         None
     }
 
+    /// Errors and warnings found while opening the archive (optional).
+    ///
+    /// Lets `open()` succeed on a damaged archive while still telling the
+    /// user what is wrong with it, e.g. a truncated index:
+    ///
+    /// ```rust,ignore
+    /// fn diagnostics(&self) -> ArchiveDiagnostics {
+    ///     ArchiveDiagnostics {
+    ///         warnings: ArchiveErrorFlags::UNEXPECTED_END,
+    ///         warning: Some(format!("{} entries could not be read", self.lost)),
+    ///         ..Default::default()
+    ///     }
+    /// }
+    /// ```
+    fn diagnostics(&self) -> ArchiveDiagnostics {
+        ArchiveDiagnostics::default()
+    }
+
+    /// Metadata about the whole archive (optional), e.g.:
+    ///
+    /// ```rust,ignore
+    /// fn archive_properties(&self) -> Vec<ArchiveProperty> {
+    ///     vec![
+    ///         ArchiveProperty::Method("Deflate".into()),
+    ///         ArchiveProperty::Solid(self.solid),
+    ///         ArchiveProperty::NumFiles(self.entries.len() as u64),
+    ///     ]
+    /// }
+    /// ```
+    ///
+    /// The physical size is always reported from `physical_size()`.
+    fn archive_properties(&self) -> Vec<ArchiveProperty> {
+        Vec::new()
+    }
+
+    /// Columns shown in 7-Zip's file list (default: `PropertyInfo::STANDARD`).
+    ///
+    /// Override to drop columns your format never fills, or to add any other
+    /// 7-Zip property or plugin-defined ones. Values come from
+    /// `item_property()`, by default the matching `ArchiveItem` fields or
+    /// `ArchiveItem::properties`:
+    ///
+    /// ```rust,ignore
+    /// const BLOCK_HASH: PropId = PropId::user_defined(0);
+    ///
+    /// fn item_properties(&self) -> Vec<PropertyInfo> {
+    ///     vec![
+    ///         PropertyInfo::new(PropId::PATH, PropType::String),
+    ///         PropertyInfo::new(PropId::SIZE, PropType::U64),
+    ///         PropertyInfo::new(PropId::METHOD, PropType::String),
+    ///         PropertyInfo::named(BLOCK_HASH, "Block Hash", PropType::String),
+    ///     ]
+    /// }
+    /// ```
+    fn item_properties(&self) -> Vec<PropertyInfo> {
+        PropertyInfo::STANDARD.to_vec()
+    }
+
     /// Open and parse an encrypted archive with password support.
     ///
     /// This is called instead of `open()` when 7-Zip provides a password callback.
@@ -112,6 +314,7 @@ pub trait ArchiveReader: ArchiveFormat {
     ///
     /// - `reader`: A seekable reader for the archive data
     /// - `size`: Total size of the archive in bytes
+    /// - `ctx`: Open progress and cancellation (see `open()`)
     /// - `password_requester`: A callback to request passwords from the user.
     ///   Call `password_requester.get_password()` when you need to decrypt.
     ///
@@ -120,9 +323,10 @@ pub trait ArchiveReader: ArchiveFormat {
         &mut self,
         reader: &mut dyn ReadSeek,
         size: u64,
+        ctx: &dyn OpenContext,
         _password_requester: Option<&dyn PasswordRequester>,
     ) -> Result<()> {
-        self.open(reader, size)
+        self.open(reader, size, ctx)
     }
 
     /// Extract an item's data with password support (streaming).
@@ -145,6 +349,90 @@ pub trait ArchiveReader: ArchiveFormat {
     ) -> Result<u64> {
         self.extract_to(index, writer)
     }
+
+    /// Verify an item's data (7-Zip's test command, `7z t`).
+    ///
+    /// Decode the item into `sink`, which discards the data and reports progress,
+    /// and check it. Return `Err(Error::ChecksumMismatch)` if the data does not
+    /// match its stored checksum.
+    ///
+    /// The default implementation extracts the item into `sink` with
+    /// `extract_to_with_password()` and compares the CRC32 of the output with
+    /// the item's `PropId::CRC` property when it has one. Override this if
+    /// your format has its own integrity checks.
+    fn test(
+        &mut self,
+        index: usize,
+        sink: &mut dyn Write,
+        password_requester: Option<&dyn PasswordRequester>,
+    ) -> Result<()> {
+        let expected = self
+            .item_property(index, PropId::CRC)
+            .and_then(|crc| crc.as_u32());
+
+        let mut writer = Crc32Writer::new(sink);
+        self.extract_to_with_password(index, &mut writer, password_requester)?;
+
+        match expected {
+            Some(crc) if crc != writer.crc() => {
+                Err(crate::error::Error::ChecksumMismatch.at_index(index))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Extract or test several items in one call.
+    ///
+    /// 7-Zip's extract and test commands end up here with every requested
+    /// index in ascending order, including items the user chose to skip.
+    /// Solid formats should override this to decode each block once and
+    /// hand every item to its own output as it comes out:
+    ///
+    /// ```rust,ignore
+    /// fn extract_many(
+    ///     &mut self,
+    ///     indices: &[usize],
+    ///     sinks: &mut dyn ExtractSinks,
+    ///     _password_requester: Option<&dyn PasswordRequester>,
+    /// ) -> Result<()> {
+    ///     for block in self.blocks_of(indices) {
+    ///         let mut decoder = self.decode_block(block)?;
+    ///         for &index in &block.items {
+    ///             let result = match sinks.open(index)? {
+    ///                 Some(writer) => decoder.copy_item(index, writer),
+    ///                 None => decoder.skip_item(index),
+    ///             };
+    ///             sinks.finish(index, result)?;
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// Requested items that were never finished are reported with the error
+    /// this returns, or as unavailable if it returns `Ok(())`.
+    ///
+    /// The default implementation handles the items one by one with
+    /// `test()` or `extract_to_with_password()`.
+    fn extract_many(
+        &mut self,
+        indices: &[usize],
+        sinks: &mut dyn ExtractSinks,
+        password_requester: Option<&dyn PasswordRequester>,
+    ) -> Result<()> {
+        let test = sinks.is_test();
+        for &index in indices {
+            let result = match sinks.open(index)? {
+                Some(writer) if test => self.test(index, writer, password_requester),
+                Some(writer) => self
+                    .extract_to_with_password(index, writer, password_requester)
+                    .map(|_| ()),
+                None => Ok(()),
+            };
+            sinks.finish(index, result)?;
+        }
+        Ok(())
+    }
 }
 
 /// Trait for writing/updating archives.
@@ -156,11 +444,15 @@ pub trait ArchiveUpdater: ArchiveReader {
     ///
     /// - `existing`: A seekable reader for the existing archive (or empty if creating new)
     /// - `existing_size`: Size of the existing archive in bytes (0 if creating new)
-    /// - `updates`: List of update operations (copy existing or add new)
+    /// - `updates`: The update plan: the items of the new archive in output order
+    ///   (copy existing, possibly renamed or with new properties, or add new),
+    ///   plus the indices of deleted items.
+    ///   The data of `AddNew` items is streamed from 7-Zip when you read it,
+    ///   so process items one at a time rather than collecting their data first.
     /// - `writer`: Output stream to write the new archive to
     /// - `progress`: Optional callback to report progress during the write phase.
-    ///   The callback receives `(completed_bytes, total_bytes)` and should return
-    ///   `true` to continue or `false` to request cancellation.
+    ///   The callback receives `(completed_bytes, total_bytes)` and returns
+    ///   `false` once the user has cancelled; stop and return `Error::Cancelled`.
     ///
     /// Returns the number of bytes written to the output.
     ///
@@ -177,7 +469,7 @@ pub trait ArchiveUpdater: ArchiveReader {
         &mut self,
         existing: &mut dyn ReadSeek,
         existing_size: u64,
-        updates: Vec<UpdateItem>,
+        updates: UpdatePlan<'_>,
         writer: &mut dyn Write,
         progress: Option<ProgressCallback<'_>>,
     ) -> Result<u64>;
@@ -190,7 +482,7 @@ pub trait ArchiveUpdater: ArchiveReader {
     ///
     /// - `existing`: A seekable reader for the existing archive (or empty if creating new)
     /// - `existing_size`: Size of the existing archive in bytes (0 if creating new)
-    /// - `updates`: List of update operations (copy existing or add new)
+    /// - `updates`: The update plan (see `update_streaming()`)
     /// - `writer`: Output stream to write the new archive to
     /// - `progress`: Optional callback to report progress during the write phase
     /// - `password_provider`: A callback to get the encryption password.
@@ -201,7 +493,7 @@ pub trait ArchiveUpdater: ArchiveReader {
         &mut self,
         existing: &mut dyn ReadSeek,
         existing_size: u64,
-        updates: Vec<UpdateItem>,
+        updates: UpdatePlan<'_>,
         writer: &mut dyn Write,
         progress: Option<ProgressCallback<'_>>,
         _password_provider: Option<&dyn PasswordProvider>,
@@ -1,5 +1,8 @@
 //! Core types for archive items and properties.
 
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{Read, Write};
 use std::time::SystemTime;
 
 /// Information about a single item (file/directory) in an archive.
@@ -25,6 +28,10 @@ pub struct ArchiveItem {
     pub crc: Option<u32>,
     /// Whether this item is encrypted (shows lock icon in 7-Zip)
     pub encrypted: bool,
+    /// Additional properties, shown in the columns declared by
+    /// `ArchiveReader::item_properties()`. These take precedence over the
+    /// fields above for the same property ID.
+    pub properties: Vec<(PropId, PropValue)>,
 }
 
 impl ArchiveItem {
@@ -89,6 +96,281 @@ impl ArchiveItem {
         self.encrypted = encrypted;
         self
     }
+
+    /// Set an additional property, replacing any previous value for `id`.
+    pub fn with_property(mut self, id: PropId, value: impl Into<PropValue>) -> Self {
+        let value = value.into();
+        match self
+            .properties
+            .iter_mut()
+            .find(|(existing, _)| *existing == id)
+        {
+            Some((_, slot)) => *slot = value,
+            None => self.properties.push((id, value)),
+        }
+        self
+    }
+
+    /// Get an additional property set with `with_property()`.
+    pub fn property(&self, id: PropId) -> Option<&PropValue> {
+        self.properties
+            .iter()
+            .find(|(existing, _)| *existing == id)
+            .map(|(_, value)| value)
+    }
+
+    /// The value shown for property `id`: an additional property if one is
+    /// set, otherwise the matching field.
+    pub fn property_value(&self, id: PropId) -> Option<PropValue> {
+        if let Some(value) = self.property(id) {
+            return Some(value.clone());
+        }
+
+        match id {
+            PropId::PATH => Some(self.name.as_str().into()),
+            PropId::SIZE => Some(self.size.into()),
+            PropId::PACK_SIZE => self.compressed_size.map(Into::into),
+            PropId::IS_DIR => Some(self.is_dir.into()),
+            PropId::MTIME => self.modified.map(Into::into),
+            PropId::CTIME => self.created.map(Into::into),
+            PropId::ATIME => self.accessed.map(Into::into),
+            PropId::ATTRIB => self.attributes.map(Into::into),
+            PropId::CRC => self.crc.map(Into::into),
+            PropId::ENCRYPTED => Some(self.encrypted.into()),
+            _ => None,
+        }
+    }
+}
+
+/// A 7-Zip property ID (`kpid*`).
+///
+/// The associated constants cover the common IDs. Any other 7-Zip ID can
+/// be used as `PropId(n)`, and plugin-specific properties use
+/// [`PropId::user_defined()`] together with a name in [`PropertyInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PropId(pub u32);
+
+impl PropId {
+    pub const PATH: Self = Self(3);
+    pub const NAME: Self = Self(4);
+    pub const EXTENSION: Self = Self(5);
+    pub const IS_DIR: Self = Self(6);
+    pub const SIZE: Self = Self(7);
+    pub const PACK_SIZE: Self = Self(8);
+    pub const ATTRIB: Self = Self(9);
+    pub const CTIME: Self = Self(10);
+    pub const ATIME: Self = Self(11);
+    pub const MTIME: Self = Self(12);
+    pub const SOLID: Self = Self(13);
+    pub const COMMENTED: Self = Self(14);
+    pub const ENCRYPTED: Self = Self(15);
+    pub const SPLIT_BEFORE: Self = Self(16);
+    pub const SPLIT_AFTER: Self = Self(17);
+    pub const DICTIONARY_SIZE: Self = Self(18);
+    pub const CRC: Self = Self(19);
+    pub const TYPE: Self = Self(20);
+    pub const IS_ANTI: Self = Self(21);
+    pub const METHOD: Self = Self(22);
+    pub const HOST_OS: Self = Self(23);
+    pub const FILE_SYSTEM: Self = Self(24);
+    pub const USER: Self = Self(25);
+    pub const GROUP: Self = Self(26);
+    pub const BLOCK: Self = Self(27);
+    pub const COMMENT: Self = Self(28);
+    pub const POSITION: Self = Self(29);
+    pub const PREFIX: Self = Self(30);
+    pub const NUM_SUB_DIRS: Self = Self(31);
+    pub const NUM_SUB_FILES: Self = Self(32);
+    pub const UNPACK_VER: Self = Self(33);
+    pub const VOLUME: Self = Self(34);
+    pub const IS_VOLUME: Self = Self(35);
+    pub const OFFSET: Self = Self(36);
+    pub const LINKS: Self = Self(37);
+    pub const NUM_BLOCKS: Self = Self(38);
+    pub const NUM_VOLUMES: Self = Self(39);
+    pub const CPU: Self = Self(43);
+    pub const PHY_SIZE: Self = Self(44);
+    pub const HEADERS_SIZE: Self = Self(45);
+    pub const CHECKSUM: Self = Self(46);
+    pub const CHARACTERISTICS: Self = Self(47);
+    pub const CREATOR_APP: Self = Self(51);
+    pub const POSIX_ATTRIB: Self = Self(53);
+    pub const SYM_LINK: Self = Self(54);
+    pub const ERROR: Self = Self(55);
+    pub const IS_ALT_STREAM: Self = Self(63);
+    pub const IS_DELETED: Self = Self(65);
+    pub const SHA1: Self = Self(67);
+    pub const SHA256: Self = Self(68);
+    pub const ERROR_FLAGS: Self = Self(71);
+    pub const WARNING_FLAGS: Self = Self(72);
+    pub const WARNING: Self = Self(73);
+    pub const HARD_LINK: Self = Self(90);
+
+    /// First ID of the range reserved for plugin-defined properties.
+    pub const USER_DEFINED: Self = Self(0x10000);
+
+    /// The `n`-th plugin-defined property ID.
+    pub const fn user_defined(n: u32) -> Self {
+        Self(Self::USER_DEFINED.0 + n)
+    }
+}
+
+/// Value of an item property.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropValue {
+    String(String),
+    U32(u32),
+    U64(u64),
+    I64(i64),
+    Bool(bool),
+    FileTime(SystemTime),
+    Bytes(Vec<u8>),
+}
+
+impl PropValue {
+    /// The type of this value.
+    pub fn prop_type(&self) -> PropType {
+        match self {
+            PropValue::String(_) => PropType::String,
+            PropValue::U32(_) => PropType::U32,
+            PropValue::U64(_) => PropType::U64,
+            PropValue::I64(_) => PropType::I64,
+            PropValue::Bool(_) => PropType::Bool,
+            PropValue::FileTime(_) => PropType::FileTime,
+            PropValue::Bytes(_) => PropType::Bytes,
+        }
+    }
+
+    /// The value as a string, if it is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            PropValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value as a `u32`, if it is one.
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            PropValue::U32(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The value as a `u64`, widening a `u32`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            PropValue::U64(v) => Some(*v),
+            PropValue::U32(v) => Some(u64::from(*v)),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for PropValue {
+    fn from(value: String) -> Self {
+        PropValue::String(value)
+    }
+}
+
+impl From<&str> for PropValue {
+    fn from(value: &str) -> Self {
+        PropValue::String(value.to_string())
+    }
+}
+
+impl From<u32> for PropValue {
+    fn from(value: u32) -> Self {
+        PropValue::U32(value)
+    }
+}
+
+impl From<u64> for PropValue {
+    fn from(value: u64) -> Self {
+        PropValue::U64(value)
+    }
+}
+
+impl From<i64> for PropValue {
+    fn from(value: i64) -> Self {
+        PropValue::I64(value)
+    }
+}
+
+impl From<bool> for PropValue {
+    fn from(value: bool) -> Self {
+        PropValue::Bool(value)
+    }
+}
+
+impl From<SystemTime> for PropValue {
+    fn from(value: SystemTime) -> Self {
+        PropValue::FileTime(value)
+    }
+}
+
+impl From<Vec<u8>> for PropValue {
+    fn from(value: Vec<u8>) -> Self {
+        PropValue::Bytes(value)
+    }
+}
+
+/// Type of the values in a property column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropType {
+    String,
+    U32,
+    U64,
+    I64,
+    Bool,
+    FileTime,
+    Bytes,
+}
+
+/// A column of 7-Zip's file list, declared by `ArchiveReader::item_properties()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyInfo {
+    /// Property ID.
+    pub id: PropId,
+    /// Column name, for plugin-defined properties 7-Zip has no name for.
+    pub name: Option<Cow<'static, str>>,
+    /// Type of the values.
+    pub prop_type: PropType,
+}
+
+impl PropertyInfo {
+    /// The columns shown by default: path, size, packed size, directory
+    /// flag, modified/created/accessed times, attributes, CRC and encrypted.
+    pub const STANDARD: &'static [PropertyInfo] = &[
+        PropertyInfo::new(PropId::PATH, PropType::String),
+        PropertyInfo::new(PropId::SIZE, PropType::U64),
+        PropertyInfo::new(PropId::PACK_SIZE, PropType::U64),
+        PropertyInfo::new(PropId::IS_DIR, PropType::Bool),
+        PropertyInfo::new(PropId::MTIME, PropType::FileTime),
+        PropertyInfo::new(PropId::CTIME, PropType::FileTime),
+        PropertyInfo::new(PropId::ATIME, PropType::FileTime),
+        PropertyInfo::new(PropId::ATTRIB, PropType::U32),
+        PropertyInfo::new(PropId::CRC, PropType::U32),
+        PropertyInfo::new(PropId::ENCRYPTED, PropType::Bool),
+    ];
+
+    /// A column for a property 7-Zip knows by ID.
+    pub const fn new(id: PropId, prop_type: PropType) -> Self {
+        Self {
+            id,
+            name: None,
+            prop_type,
+        }
+    }
+
+    /// A named column, for plugin-defined properties.
+    pub fn named(id: PropId, name: impl Into<Cow<'static, str>>, prop_type: PropType) -> Self {
+        Self {
+            id,
+            name: Some(name.into()),
+            prop_type,
+        }
+    }
 }
 
 /// Progress callback for archive operations.
@@ -97,9 +379,33 @@ impl ArchiveItem {
 /// - `completed`: Number of bytes processed so far
 /// - `total`: Total number of bytes to process
 ///
-/// Return `true` to continue the operation, or `false` to request cancellation.
+/// Returns `true` to continue the operation, or `false` if the user cancelled it
+/// in 7-Zip. On `false`, stop as soon as possible and return `Error::Cancelled`.
 pub type ProgressCallback<'a> = &'a mut dyn FnMut(u64, u64) -> bool;
 
+/// Progress reporting and cancellation while an archive is being opened.
+///
+/// Passed to `ArchiveReader::open()` so formats that scan large indexes can
+/// tell 7-Zip how far they are. Either count may be `None` if it is not known.
+pub trait OpenContext {
+    /// Report the total number of files and/or bytes to process.
+    fn set_total(&self, files: Option<u64>, bytes: Option<u64>) -> crate::error::Result<()>;
+
+    /// Report the number of files and/or bytes processed so far.
+    ///
+    /// Returns `Err(Error::Cancelled)` once the user has cancelled in 7-Zip;
+    /// propagate it with `?` to abort the open.
+    fn set_completed(&self, files: Option<u64>, bytes: Option<u64>) -> crate::error::Result<()>;
+
+    /// Whether the user has cancelled the open operation.
+    ///
+    /// Asks 7-Zip each time it is called (like a `set_completed()` that
+    /// reports no new counts), so it can be polled from loops that have no
+    /// progress to report. Once it returns `true`, stop and return
+    /// `Err(Error::Cancelled)`.
+    fn is_cancelled(&self) -> bool;
+}
+
 /// A trait for requesting passwords from 7-Zip's UI.
 ///
 /// This is passed to archive open/extract methods when the user may need
@@ -109,7 +415,8 @@ pub trait PasswordRequester {
     ///
     /// Returns:
     /// - `Ok(Some(password))` - User provided a password
-    /// - `Ok(None)` - No password available (user cancelled or not supported)
+    /// - `Ok(None)` - No password available (not supported)
+    /// - `Err(Error::Cancelled)` - User cancelled the password prompt
     /// - `Err(_)` - Error occurred while getting password
     fn get_password(&self) -> crate::error::Result<Option<String>>;
 }
@@ -124,25 +431,400 @@ pub trait PasswordProvider {
     /// Returns:
     /// - `Ok(Some(password))` - User wants encryption with this password
     /// - `Ok(None)` - No encryption requested
+    /// - `Err(Error::Cancelled)` - User cancelled the password prompt
     /// - `Err(_)` - Error occurred while getting password
     fn get_password(&self) -> crate::error::Result<Option<String>>;
 }
 
+/// Output streams for the items of an `ArchiveReader::extract_many()` call.
+///
+/// Items are written one at a time: `open()` an item, write its data, then
+/// `finish()` it with the result of decoding it. Items may be handled in any
+/// order, e.g. grouped by solid block.
+///
+/// `open()` and `finish()` call into 7-Zip, which may call back into the
+/// archive from there, e.g. to read the path of the item it is about to
+/// write. The plugin is busy in `extract_many()` at that point, so those
+/// reads are answered from the properties of the requested items captured
+/// before `extract_many()` was called; changes the plugin makes to its
+/// items while extracting are not seen by 7-Zip until the call returns.
+pub trait ExtractSinks {
+    /// Whether 7-Zip is testing (`7z t`) rather than extracting.
+    ///
+    /// In test mode every item gets a writer that discards its data, and
+    /// items should be verified as in `ArchiveReader::test()`.
+    fn is_test(&self) -> bool;
+
+    /// Open the output for item `index`.
+    ///
+    /// Returns `Ok(None)` if 7-Zip skips the item (e.g. the user chose not to
+    /// overwrite an existing file). A solid decoder still has to decode a
+    /// skipped item's data to reach the items after it, it just discards it.
+    ///
+    /// Each requested item can be opened once; opening another item before
+    /// finishing this one fails it. Returns `Err(Error::Cancelled)` once the
+    /// user has cancelled.
+    fn open(&mut self, index: usize) -> crate::error::Result<Option<&mut dyn Write>>;
+
+    /// Finish the open item `index` with the outcome of decoding it.
+    ///
+    /// Fails if `index` is not the item currently open.
+    ///
+    /// An `Err` result is reported to 7-Zip for this item only (e.g. as a CRC
+    /// error), and the remaining items can still be extracted. Returns
+    /// `Err(Error::Cancelled)` once the user has cancelled; propagate it with `?`.
+    fn finish(
+        &mut self,
+        index: usize,
+        result: crate::error::Result<()>,
+    ) -> crate::error::Result<()>;
+}
+
+/// Data source for a new item being added to an archive.
+///
+/// The data is pulled from 7-Zip on demand: the host's input stream is only
+/// opened when the updater first reads from it, so memory use stays bounded
+/// regardless of file size. Read it like any other `std::io::Read`.
+pub struct ItemData<'a> {
+    reader: Box<dyn Read + 'a>,
+}
+
+impl<'a> ItemData<'a> {
+    /// Create an item data source from any reader.
+    pub fn new(reader: impl Read + 'a) -> Self {
+        Self {
+            reader: Box::new(reader),
+        }
+    }
+
+    /// Create an empty data source.
+    pub fn empty() -> Self {
+        Self::new(std::io::empty())
+    }
+}
+
+impl Read for ItemData<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl fmt::Debug for ItemData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ItemData").finish_non_exhaustive()
+    }
+}
+
 /// Describes an update operation for archive editing.
-#[derive(Debug, Clone)]
-pub enum UpdateItem {
+#[derive(Debug)]
+pub enum UpdateItem<'a> {
     /// Copy an existing item from the source archive by index.
+    ///
+    /// The item's data is unchanged, so it can be copied without recompressing.
     CopyExisting {
         /// Index of the item in the original archive
         index: usize,
         /// New name (if renaming), or None to keep original
         new_name: Option<String>,
+        /// New metadata (path, timestamps, attributes) if 7-Zip changed the
+        /// item's properties, or None to keep the original header as-is
+        new_props: Option<ArchiveItem>,
     },
-    /// Add new data to the archive.
+    /// Add a new file or directory to the archive.
     AddNew {
-        /// Name/path for the new item
-        name: String,
-        /// The data to add
-        data: Vec<u8>,
+        /// Metadata of the new item as reported by 7-Zip: path, declared size,
+        /// timestamps, attributes and the directory flag
+        item: ArchiveItem,
+        /// The data to add, streamed from 7-Zip as it is read (empty for directories)
+        data: ItemData<'a>,
     },
 }
+
+/// The complete set of changes 7-Zip requested for an update.
+#[derive(Debug, Default)]
+pub struct UpdatePlan<'a> {
+    /// Items of the new archive, in output order.
+    pub items: Vec<UpdateItem<'a>>,
+    /// Indices of items in the original archive that are being deleted.
+    pub deleted: Vec<usize>,
+}
+
+impl UpdatePlan<'_> {
+    /// Returns `true` if no item carries new data.
+    ///
+    /// In that case only deletions, renames and property changes are requested,
+    /// so an updater can patch headers without recompressing anything.
+    pub fn is_metadata_only(&self) -> bool {
+        self.items
+            .iter()
+            .all(|item| matches!(item, UpdateItem::CopyExisting { .. }))
+    }
+}
+
+/// Common methods and `|` operators for the bit flag types below.
+macro_rules! impl_flags {
+    ($name:ident) => {
+        impl $name {
+            /// The raw bits, as passed to 7-Zip.
+            pub const fn bits(self) -> u32 {
+                self.0
+            }
+
+            /// Returns `true` if no flag is set.
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            /// Returns `true` if all flags in `other` are set.
+            pub const fn contains(self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+        }
+
+        impl std::ops::BitOr for $name {
+            type Output = Self;
+
+            fn bitor(self, rhs: Self) -> Self {
+                Self(self.0 | rhs.0)
+            }
+        }
+
+        impl std::ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, rhs: Self) {
+                self.0 |= rhs.0;
+            }
+        }
+    };
+}
+
+/// Metadata about the archive as a whole, returned from
+/// `ArchiveReader::archive_properties()`.
+///
+/// 7-Zip shows these in `7z l -slt` and the File Manager's Properties dialog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveProperty {
+    /// Archive comment.
+    Comment(String),
+    /// Compression method(s), e.g. "LZMA2:24".
+    Method(String),
+    /// Whether the archive is solid.
+    Solid(bool),
+    /// Number of solid blocks.
+    NumBlocks(u64),
+    /// Size of the archive headers in bytes.
+    HeadersSize(u64),
+    /// Archive creation time.
+    Created(SystemTime),
+    /// Archive modification time.
+    Modified(SystemTime),
+    /// Whether the archive headers are encrypted.
+    EncryptedHeaders(bool),
+    /// Offset of the archive within the file (e.g. after an SFX stub).
+    Offset(u64),
+    /// Number of files.
+    NumFiles(u64),
+    /// Number of folders.
+    NumFolders(u64),
+    /// Any other 7-Zip archive property.
+    Other(PropId, PropValue),
+}
+
+impl ArchiveProperty {
+    /// The 7-Zip property ID.
+    pub fn id(&self) -> PropId {
+        match self {
+            ArchiveProperty::Comment(_) => PropId::COMMENT,
+            ArchiveProperty::Method(_) => PropId::METHOD,
+            ArchiveProperty::Solid(_) => PropId::SOLID,
+            ArchiveProperty::NumBlocks(_) => PropId::NUM_BLOCKS,
+            ArchiveProperty::HeadersSize(_) => PropId::HEADERS_SIZE,
+            ArchiveProperty::Created(_) => PropId::CTIME,
+            ArchiveProperty::Modified(_) => PropId::MTIME,
+            ArchiveProperty::EncryptedHeaders(_) => PropId::ENCRYPTED,
+            ArchiveProperty::Offset(_) => PropId::OFFSET,
+            ArchiveProperty::NumFiles(_) => PropId::NUM_SUB_FILES,
+            ArchiveProperty::NumFolders(_) => PropId::NUM_SUB_DIRS,
+            ArchiveProperty::Other(id, _) => *id,
+        }
+    }
+
+    /// The property value.
+    pub fn value(&self) -> PropValue {
+        match self {
+            ArchiveProperty::Comment(s) | ArchiveProperty::Method(s) => {
+                PropValue::String(s.clone())
+            }
+            ArchiveProperty::Solid(b) | ArchiveProperty::EncryptedHeaders(b) => PropValue::Bool(*b),
+            ArchiveProperty::NumBlocks(n)
+            | ArchiveProperty::HeadersSize(n)
+            | ArchiveProperty::Offset(n)
+            | ArchiveProperty::NumFiles(n)
+            | ArchiveProperty::NumFolders(n) => PropValue::U64(*n),
+            ArchiveProperty::Created(t) | ArchiveProperty::Modified(t) => PropValue::FileTime(*t),
+            ArchiveProperty::Other(_, value) => value.clone(),
+        }
+    }
+}
+
+/// A file extension of a format, as listed in `ArchiveFormat::extensions()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatExtension {
+    /// Extension without the dot (e.g. "pak").
+    pub ext: &'static str,
+    /// Extension given to the extracted file, for compressor-style formats
+    /// (e.g. "tar" so that `foo.tgz` extracts to `foo.tar`).
+    pub add_ext: Option<&'static str>,
+}
+
+impl FormatExtension {
+    /// An extension with no add-extension.
+    pub const fn new(ext: &'static str) -> Self {
+        Self { ext, add_ext: None }
+    }
+
+    /// Set the extension given to the extracted file.
+    pub const fn with_add_extension(mut self, add_ext: &'static str) -> Self {
+        self.add_ext = Some(add_ext);
+        self
+    }
+}
+
+/// How precisely a format stores file times.
+///
+/// Returned from `ArchiveFormat::time_precision()`. 7-Zip compares times at
+/// this precision when deciding which files changed during an update.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimePrecision {
+    /// Windows FILETIME: 100ns steps.
+    #[default]
+    Windows,
+    /// Unix time: whole seconds.
+    Unix,
+    /// MS-DOS time: 2-second steps, years 1980-2107.
+    Dos,
+    /// Nanoseconds.
+    Nanoseconds,
+}
+
+/// Result of quickly checking whether some data starts an archive of a format.
+///
+/// Returned from `ArchiveFormat::detect()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection {
+    /// The data is not in this format.
+    No,
+    /// The data looks like this format.
+    Yes,
+    /// Not enough data was given to decide.
+    NeedMoreData,
+}
+
+/// Capabilities and open behaviour of a format, as declared to 7-Zip.
+///
+/// Returned from `ArchiveFormat::flags()`. Combine flags with `|`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FormatFlags(u32);
+
+impl FormatFlags {
+    /// No flags.
+    pub const NONE: Self = Self(0);
+    /// Keep the archive name when extracting (compressors like gzip).
+    pub const KEEP_NAME: Self = Self(1 << 0);
+    /// Items can have alternate data streams.
+    pub const ALT_STREAMS: Self = Self(1 << 1);
+    /// Items can carry NT security descriptors.
+    pub const NT_SECURE: Self = Self(1 << 2);
+    /// Search for the signature anywhere in the file, not only at the start.
+    pub const FIND_SIGNATURE: Self = Self(1 << 3);
+    /// Archive offsets are relative to the start of the file, not the signature.
+    pub const USE_GLOBAL_OFFSET: Self = Self(1 << 5);
+    /// Try to open the file even if no signature matches.
+    pub const START_OPEN: Self = Self(1 << 6);
+    /// Like `START_OPEN`, but only at the start of the file.
+    pub const PURE_START_OPEN: Self = Self(1 << 7);
+    /// The archive is found from the end of the file (e.g. a trailing index).
+    pub const BACKWARD_OPEN: Self = Self(1 << 8);
+    /// The format can precede another archive (e.g. a self-extractor stub).
+    pub const PRE_ARC: Self = Self(1 << 9);
+    /// Items can be symbolic links.
+    pub const SYMLINKS: Self = Self(1 << 10);
+    /// Items can be hard links.
+    pub const HARDLINKS: Self = Self(1 << 11);
+    /// Only open files with one of this format's extensions.
+    pub const BY_EXT_ONLY_OPEN: Self = Self(1 << 12);
+    /// Items can store a creation time.
+    pub const CTIME: Self = Self(1 << 14);
+    /// Creation times are stored by default when updating.
+    pub const CTIME_DEFAULT: Self = Self(1 << 15);
+    /// Items can store a last access time.
+    pub const ATIME: Self = Self(1 << 16);
+    /// Access times are stored by default when updating.
+    pub const ATIME_DEFAULT: Self = Self(1 << 17);
+    /// Items can store a modification time.
+    pub const MTIME: Self = Self(1 << 18);
+    /// Modification times are stored by default when updating.
+    pub const MTIME_DEFAULT: Self = Self(1 << 19);
+}
+
+impl_flags!(FormatFlags);
+
+/// Problems found in an archive, as reported to 7-Zip.
+///
+/// These map directly to 7-Zip's archive error and warning flags, so
+/// 7-Zip shows a matching message (e.g. "Headers Error").
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ArchiveErrorFlags(u32);
+
+impl ArchiveErrorFlags {
+    /// No problems.
+    pub const NONE: Self = Self(0);
+    /// The archive headers are damaged.
+    pub const HEADERS_ERROR: Self = Self(1 << 1);
+    /// The headers are encrypted and could not be decrypted.
+    pub const ENCRYPTED_HEADERS_ERROR: Self = Self(1 << 2);
+    /// The start of the archive is missing (e.g. a later volume).
+    pub const UNAVAILABLE_START: Self = Self(1 << 3);
+    /// The start of the archive could not be confirmed.
+    pub const UNCONFIRMED_START: Self = Self(1 << 4);
+    /// The archive ends before all of its data.
+    pub const UNEXPECTED_END: Self = Self(1 << 5);
+    /// There is data after the end of the archive.
+    pub const DATA_AFTER_END: Self = Self(1 << 6);
+    /// The archive uses a compression or encryption method that is not supported.
+    pub const UNSUPPORTED_METHOD: Self = Self(1 << 7);
+    /// The archive uses a feature that is not supported.
+    pub const UNSUPPORTED_FEATURE: Self = Self(1 << 8);
+    /// The archive data is damaged.
+    pub const DATA_ERROR: Self = Self(1 << 9);
+    /// A checksum does not match.
+    pub const CRC_ERROR: Self = Self(1 << 10);
+}
+
+impl_flags!(ArchiveErrorFlags);
+
+/// Errors and warnings recorded while opening a damaged archive.
+///
+/// Returned from `ArchiveReader::diagnostics()` after a successful open.
+/// 7-Zip shows them to the user (e.g. "Warnings: Headers Error") while still
+/// listing and extracting whatever was recovered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveDiagnostics {
+    /// Errors that make the archive unreliable.
+    pub errors: ArchiveErrorFlags,
+    /// Problems that did not prevent reading the archive.
+    pub warnings: ArchiveErrorFlags,
+    /// Free-text error message.
+    pub error: Option<String>,
+    /// Free-text warning message.
+    pub warning: Option<String>,
+}
+
+impl ArchiveDiagnostics {
+    /// Returns `true` if nothing was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+            && self.warnings.is_empty()
+            && self.error.is_none()
+            && self.warning.is_none()
+    }
+}
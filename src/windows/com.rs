@@ -12,8 +12,8 @@ use cppvtable::IUnknown;
 pub use cppvtable::com::GUID;
 use cppvtable::proc::com_interface;
 
-// Re-export HRESULT from windows crate (cppvtable's HRESULT is a wrapper, windows uses it directly)
-pub use windows::core::HRESULT;
+// Re-export HRESULT from windows-core (cppvtable's HRESULT is a wrapper, windows uses it directly)
+pub use super::sys::HRESULT;
 
 /// Create a 7-Zip format GUID from the format ID byte.
 pub const fn make_format_guid(id: u8) -> GUID {
@@ -320,29 +320,6 @@ pub trait IArchiveOpenCallback<T> {
     fn set_completed(&self, files: *const u64, bytes: *const u64) -> HRESULT;
 }
 
-/// Property IDs used by 7-Zip.
-#[repr(u32)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum PropId {
-    Path = 3,
-    IsDir = 6,
-    Size = 7,
-    PackSize = 8,
-    Attrib = 9,
-    CTime = 10,
-    ATime = 11,
-    MTime = 12,
-    Crc = 19,
-    Encrypted = 21,
-}
-
-/// Archive property IDs.
-#[repr(u32)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ArchivePropId {
-    PhySize = 4,
-}
-
 /// Handler property IDs for GetHandlerProperty2.
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -359,4 +336,5 @@ pub enum HandlerPropId {
     AltStreams = 9,
     NtSecure = 10,
     Flags = 11,
+    TimeFlags = 12,
 }
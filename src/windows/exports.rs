@@ -4,11 +4,14 @@
 //! DLL exports for a 7-Zip plugin.
 
 use std::ffi::c_void;
-use windows::Win32::Foundation::{CLASS_E_CLASSNOTAVAILABLE, E_INVALIDARG, S_OK};
-use windows::core::{GUID, HRESULT};
 
-use super::com::{HandlerPropId, IID_IINARCHIVE, IID_IOUTARCHIVE};
-use super::propvariant::RawPropVariant;
+use super::com::HandlerPropId;
+use super::handler::FormatRegistration;
+use super::panic::guard;
+use super::propvariant::{RawPropVariant, time_precision_code};
+use super::sys::{CLASS_E_CLASSNOTAVAILABLE, E_FAIL, E_INVALIDARG, GUID, HRESULT, S_OK};
+use crate::types::{Detection, FormatExtension, FormatFlags};
+use crate::{log_debug, log_warn};
 
 /// Macro to register a format and generate all required DLL exports.
 ///
@@ -29,48 +32,77 @@ use super::propvariant::RawPropVariant;
 /// // Format that supports editing (requires ArchiveUpdater impl)
 /// sevenzip_plugin::register_format!(MyFormat, updatable);
 /// ```
+///
+/// To register more than one format from the same DLL, use [`register_formats!`].
 #[macro_export]
 macro_rules! register_format {
-    // Internal implementation - shared by both variants
-    (@impl $format:ty, $out_vtbl_fn:path) => {
-        static IN_VTBL: $crate::windows::com::IInArchiveVTable<
-            $crate::windows::handler::PluginHandler<$format>,
-        > = $crate::windows::handler::create_in_vtable::<$format>();
+    // Read-only format (no updatable flag)
+    ($format:ty) => {
+        $crate::register_formats!($format);
+    };
+
+    // Updatable format (with updatable flag)
+    ($format:ty, updatable) => {
+        $crate::register_formats!($format: updatable);
+    };
+}
+
+/// Macro to register several formats in one plugin DLL and generate the exports.
+///
+/// Formats are exposed to 7-Zip in the order given. `CreateObject` picks the
+/// handler by class ID, so every format must have a distinct `CLASS_ID`;
+/// duplicates are rejected at compile time.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// // `PakFormat` implements ArchiveUpdater, the others are read-only
+/// sevenzip_plugin::register_formats!(PakFormat: updatable, IdxFormat, BinFormat);
+/// ```
+#[macro_export]
+macro_rules! register_formats {
+    // Out vtable selection for a single entry
+    (@out_vtbl $format:ty) => {
+        $crate::windows::handler::create_out_vtable_stub::<$format>()
+    };
+    (@out_vtbl $format:ty, updatable) => {
+        $crate::windows::handler::create_out_vtable::<$format>()
+    };
+
+    ($($format:ty $(: $updatable:ident)?),+ $(,)?) => {
+        const _: () = $crate::windows::exports::assert_unique_class_ids(&[
+            $(<$format as $crate::ArchiveFormat>::CLASS_ID),+
+        ]);
+
+        static FORMATS: &[&dyn $crate::windows::handler::FormatRegistration] = &[$({
+            static IN_VTBL: $crate::windows::com::IInArchiveVTable<
+                $crate::windows::handler::PluginHandler<$format>,
+            > = $crate::windows::handler::create_in_vtable::<$format>();
 
-        static OUT_VTBL: $crate::windows::com::IOutArchiveVTable<
-            $crate::windows::handler::PluginHandler<$format>,
-        > = $out_vtbl_fn();
+            static OUT_VTBL: $crate::windows::com::IOutArchiveVTable<
+                $crate::windows::handler::PluginHandler<$format>,
+            > = $crate::register_formats!(@out_vtbl $format $(, $updatable)?);
 
-        static REGISTERED_FORMAT: $crate::windows::handler::RegisteredFormat<$format> =
-            $crate::windows::handler::RegisteredFormat::new(&IN_VTBL, &OUT_VTBL);
+            static REGISTERED_FORMAT: $crate::windows::handler::RegisteredFormat<$format> =
+                $crate::windows::handler::RegisteredFormat::new(&IN_VTBL, &OUT_VTBL);
+
+            &REGISTERED_FORMAT
+        }),+];
 
         #[unsafe(no_mangle)]
         pub unsafe extern "system" fn CreateObject(
-            clsid: *const $crate::windows_crate::core::GUID,
-            iid: *const $crate::windows_crate::core::GUID,
+            clsid: *const $crate::windows::sys::GUID,
+            iid: *const $crate::windows::sys::GUID,
             out_object: *mut *mut ::std::ffi::c_void,
-        ) -> $crate::windows_crate::core::HRESULT {
-            unsafe {
-                $crate::windows::exports::create_object::<$format>(
-                    clsid,
-                    iid,
-                    out_object,
-                    &REGISTERED_FORMAT,
-                )
-            }
+        ) -> $crate::windows::sys::HRESULT {
+            unsafe { $crate::windows::exports::create_object(clsid, iid, out_object, FORMATS) }
         }
 
         #[unsafe(no_mangle)]
         pub unsafe extern "system" fn GetNumberOfFormats(
             num_formats: *mut u32,
-        ) -> $crate::windows_crate::core::HRESULT {
-            unsafe {
-                if num_formats.is_null() {
-                    return $crate::windows_crate::Win32::Foundation::E_INVALIDARG;
-                }
-                *num_formats = 1;
-                $crate::windows_crate::Win32::Foundation::S_OK
-            }
+        ) -> $crate::windows::sys::HRESULT {
+            unsafe { $crate::windows::exports::get_number_of_formats(num_formats, FORMATS) }
         }
 
         #[unsafe(no_mangle)]
@@ -78,47 +110,25 @@ macro_rules! register_format {
             format_index: u32,
             prop_id: u32,
             value: *mut ::std::ffi::c_void,
-        ) -> $crate::windows_crate::core::HRESULT {
+        ) -> $crate::windows::sys::HRESULT {
             unsafe {
-                $crate::windows::exports::get_handler_property2::<$format>(
+                $crate::windows::exports::get_handler_property2(
                     format_index,
                     prop_id,
                     value,
+                    FORMATS,
                 )
             }
         }
-    };
 
-    // Read-only format (no updatable flag)
-    ($format:ty) => {
-        $crate::register_format!(@impl $format, $crate::windows::handler::create_out_vtable_stub::<$format>);
-    };
-
-    // Updatable format (with updatable flag)
-    ($format:ty, updatable) => {
-        $crate::register_format!(@impl $format, $crate::windows::handler::create_out_vtable::<$format>);
-    };
-}
-
-/// Log a message to the debug file (if debug feature is enabled).
-/// Uses a macro to ensure format arguments are not evaluated in release builds.
-#[cfg(feature = "debug")]
-macro_rules! log_debug {
-    ($($arg:tt)*) => {{
-        use std::io::Write;
-        if let Ok(mut file) = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("C:\\temp\\7zip-plugin-debug.log")
-        {
-            let _ = writeln!(file, $($arg)*);
+        #[unsafe(no_mangle)]
+        pub unsafe extern "system" fn GetIsArc(
+            format_index: u32,
+            is_arc: *mut ::std::option::Option<$crate::windows::exports::IsArcFunc>,
+        ) -> $crate::windows::sys::HRESULT {
+            unsafe { $crate::windows::exports::get_is_arc(format_index, is_arc, FORMATS) }
         }
-    }};
-}
-
-#[cfg(not(feature = "debug"))]
-macro_rules! log_debug {
-    ($($arg:tt)*) => {};
+    };
 }
 
 /// Convert 16-byte array to GUID at compile time.
@@ -133,31 +143,49 @@ pub const fn guid_from_bytes(bytes: &[u8; 16]) -> GUID {
     }
 }
 
-/// Implementation of CreateObject for a registered format.
+/// Compile-time check that no two registered formats share a class ID.
+///
+/// Called from a `const` item generated by [`register_formats!`], so a
+/// duplicate fails the build instead of silently shadowing a format.
+pub const fn assert_unique_class_ids(ids: &[[u8; 16]]) {
+    let mut i = 0;
+    while i < ids.len() {
+        let mut j = i + 1;
+        while j < ids.len() {
+            let mut k = 0;
+            while k < 16 && ids[i][k] == ids[j][k] {
+                k += 1;
+            }
+            if k == 16 {
+                panic!("register_formats!: two formats share the same CLASS_ID");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Implementation of CreateObject for the registered formats.
 ///
 /// # Safety
 /// - `clsid`, `iid`, and `out_object` must be valid pointers if non-null
 /// - The caller must ensure proper COM reference counting
-pub unsafe fn create_object<T: crate::ArchiveReader>(
+pub unsafe fn create_object(
     clsid: *const GUID,
     iid: *const GUID,
     out_object: *mut *mut c_void,
-    format: &'static super::handler::RegisteredFormat<T>,
+    formats: &[&dyn FormatRegistration],
 ) -> HRESULT {
-    unsafe {
-        log_debug!("CreateObject called");
-
+    guard(format_args!("CreateObject"), E_FAIL, || unsafe {
         if clsid.is_null() || iid.is_null() || out_object.is_null() {
-            log_debug!("CreateObject: null pointer");
+            log_warn!("CreateObject: null pointer");
             return E_INVALIDARG;
         }
 
         let clsid = &*clsid;
         let iid = &*iid;
 
-        // Check if the CLSID matches our format (computed at compile time per monomorphization)
         const { assert!(std::mem::size_of::<GUID>() == 16) };
-        let format_guid = guid_from_bytes(&T::class_id());
 
         log_debug!(
             "CreateObject: clsid={{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
@@ -173,94 +201,296 @@ pub unsafe fn create_object<T: crate::ArchiveReader>(
             clsid.data4[6],
             clsid.data4[7]
         );
-        log_debug!(
-            "CreateObject: format_guid={{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
-            format_guid.data1,
-            format_guid.data2,
-            format_guid.data3,
-            format_guid.data4[0],
-            format_guid.data4[1],
-            format_guid.data4[2],
-            format_guid.data4[3],
-            format_guid.data4[4],
-            format_guid.data4[5],
-            format_guid.data4[6],
-            format_guid.data4[7]
-        );
 
-        if *clsid != format_guid {
-            log_debug!("CreateObject: CLSID mismatch!");
+        // Pick the format whose class ID matches the requested CLSID
+        let Some(format) = formats.iter().find(|f| f.class_id() == *clsid) else {
+            log_warn!("CreateObject: CLSID mismatch!");
             *out_object = std::ptr::null_mut();
             return CLASS_E_CLASSNOTAVAILABLE;
-        }
-
-        // Create handler
-        let handler = format.create_handler();
+        };
 
-        // Return appropriate interface
-        if *iid == IID_IINARCHIVE {
-            *out_object = handler;
-            return S_OK;
-        }
-
-        if *iid == IID_IOUTARCHIVE && T::supports_write() {
-            // Return pointer to out_vtbl field
-            let handler_ptr = handler as *mut super::handler::PluginHandler<T>;
-            *out_object = &(*handler_ptr).out_vtbl as *const _ as *mut c_void;
-            return S_OK;
-        }
-
-        // Unknown interface
-        *out_object = std::ptr::null_mut();
-        CLASS_E_CLASSNOTAVAILABLE
-    }
+        format.create_object(iid, out_object)
+    })
 }
 
-/// Implementation of GetHandlerProperty2 for a format.
-pub fn get_handler_property2<T: crate::ArchiveFormat>(
-    format_index: u32,
+/// Write a single handler property for format `T`.
+///
+/// # Safety
+/// `prop` must be a valid PROPVARIANT owned by the caller.
+pub unsafe fn set_handler_property<T: crate::ArchiveFormat>(
     prop_id: u32,
-    value: *mut c_void,
-) -> HRESULT {
+    prop: &mut RawPropVariant,
+) {
     unsafe {
-        if format_index != 0 || value.is_null() {
-            return E_INVALIDARG;
-        }
-
-        let prop = &mut *(value as *mut RawPropVariant);
-
         match prop_id {
             x if x == HandlerPropId::Name as u32 => {
                 prop.set_bstr(T::name());
             }
             x if x == HandlerPropId::ClassId as u32 => {
                 // Return GUID as binary blob
-                let guid_bytes = T::class_id();
-                prop.set_guid(&guid_bytes);
+                prop.set_guid(&T::CLASS_ID);
             }
-            x if x == HandlerPropId::Extension as u32 => {
-                prop.set_bstr(T::extension());
+            x if x == HandlerPropId::Extension as u32 => match T::extensions() {
+                [] => prop.set_bstr(T::extension()),
+                exts => prop.set_bstr(&join_extensions(exts, |e| Some(e.ext))),
+            },
+            x if x == HandlerPropId::AddExtension as u32 => {
+                let exts = T::extensions();
+                if exts.iter().any(|e| e.add_ext.is_some()) {
+                    prop.set_bstr(&join_extensions(exts, |e| e.add_ext));
+                } else {
+                    prop.set_empty();
+                }
             }
             x if x == HandlerPropId::Update as u32 => {
                 prop.set_bool(T::supports_write());
             }
             x if x == HandlerPropId::Signature as u32 => {
-                // Return signature bytes for format auto-detection
-                if let Some(sig) = T::signature() {
-                    prop.set_bytes(sig);
+                // Return signature bytes for format auto-detection.
+                // 7-Zip ignores MultiSignature when this is set, so it is
+                // only used for formats with a single signature.
+                match format_signatures::<T>().as_slice() {
+                    [sig] => prop.set_bytes(sig),
+                    _ => prop.set_empty(),
+                }
+            }
+            x if x == HandlerPropId::MultiSignature as u32 => {
+                let sigs = format_signatures::<T>();
+                if sigs.len() > 1 {
+                    prop.set_bytes(&encode_multi_signature(&sigs));
                 } else {
                     prop.set_empty();
                 }
             }
             x if x == HandlerPropId::SignatureOffset as u32 => {
-                // Signature starts at offset 0 by default
-                prop.set_u32(0);
+                prop.set_u32(T::signature_offset());
+            }
+            x if x == HandlerPropId::Flags as u32 => {
+                let mut flags = T::flags().bits();
+                if format_signatures::<T>().len() > 1 {
+                    flags |= ARC_FLAGS_MULTI_SIGNATURE;
+                }
+                prop.set_u32(flags);
+            }
+            x if x == HandlerPropId::TimeFlags as u32 => {
+                // Supported precisions as a bit mask, default precision in bits 27..32
+                let precision = u32::from(time_precision_code(T::time_precision()));
+                prop.set_u32((1 << precision) | (precision << TIME_FLAGS_DEFAULT_PREC_SHIFT));
+            }
+            x if x == HandlerPropId::KeepName as u32 => {
+                prop.set_bool(T::flags().contains(FormatFlags::KEEP_NAME));
+            }
+            x if x == HandlerPropId::AltStreams as u32 => {
+                prop.set_bool(T::flags().contains(FormatFlags::ALT_STREAMS));
+            }
+            x if x == HandlerPropId::NtSecure as u32 => {
+                prop.set_bool(T::flags().contains(FormatFlags::NT_SECURE));
             }
             _ => {
                 prop.set_empty();
             }
         }
+    }
+}
+
+/// Join one field of each extension in the space-separated form 7-Zip
+/// expects, with `*` standing for "none".
+fn join_extensions(
+    exts: &[FormatExtension],
+    field: impl Fn(&FormatExtension) -> Option<&'static str>,
+) -> String {
+    exts.iter()
+        .map(|e| field(e).unwrap_or("*"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Bit index of the default precision in the TimeFlags handler property.
+const TIME_FLAGS_DEFAULT_PREC_SHIFT: u32 = 27;
+
+/// NArcInfoFlags::kMultiSignature, set automatically for formats with several signatures.
+const ARC_FLAGS_MULTI_SIGNATURE: u32 = 1 << 4;
+
+/// The format's signatures, from `signatures()` or else `signature()`.
+fn format_signatures<T: crate::ArchiveFormat>() -> Vec<&'static [u8]> {
+    match T::signatures() {
+        [] => T::signature().into_iter().collect(),
+        sigs => sigs.to_vec(),
+    }
+}
+
+/// Encode signatures in 7-Zip's MultiSignature form: each one prefixed
+/// with its length byte.
+fn encode_multi_signature(sigs: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for sig in sigs {
+        debug_assert!(
+            sig.len() <= u8::MAX as usize,
+            "signature longer than 255 bytes"
+        );
+        let len = sig.len().min(u8::MAX as usize);
+        out.push(len as u8);
+        out.extend_from_slice(&sig[..len]);
+    }
+    out
+}
+
+/// 7-Zip's `Func_IsArc`: checks the first bytes of a file for one format.
+pub type IsArcFunc = unsafe extern "system" fn(data: *const u8, size: usize) -> u32;
+
+// IsArc results (k_IsArc_Res_*)
+const IS_ARC_NO: u32 = 0;
+const IS_ARC_YES: u32 = 1;
+const IS_ARC_NEED_MORE: u32 = 2;
+
+/// IsArc function for format `T`, forwarding to `ArchiveFormat::detect()`.
+///
+/// # Safety
+/// `data` must point to `size` readable bytes if non-null.
+pub unsafe extern "system" fn is_arc<T: crate::ArchiveFormat>(data: *const u8, size: usize) -> u32 {
+    let header = if data.is_null() {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(data, size) }
+    };
 
+    guard(
+        format_args!("IsArc(size={})", size),
+        IS_ARC_NO,
+        || match T::detect(header) {
+            Detection::No => IS_ARC_NO,
+            Detection::Yes => IS_ARC_YES,
+            Detection::NeedMoreData => IS_ARC_NEED_MORE,
+        },
+    )
+}
+
+/// Implementation of GetIsArc for the registered formats.
+///
+/// # Safety
+/// `is_arc` must be a valid pointer if non-null.
+pub unsafe fn get_is_arc(
+    format_index: u32,
+    is_arc: *mut Option<IsArcFunc>,
+    formats: &[&dyn FormatRegistration],
+) -> HRESULT {
+    guard(
+        format_args!("GetIsArc(format_index={})", format_index),
+        E_FAIL,
+        || unsafe {
+            if is_arc.is_null() {
+                return E_INVALIDARG;
+            }
+
+            let Some(format) = formats.get(format_index as usize) else {
+                *is_arc = None;
+                return E_INVALIDARG;
+            };
+
+            *is_arc = Some(format.is_arc_func());
+            S_OK
+        },
+    )
+}
+
+/// Implementation of GetNumberOfFormats for the registered formats.
+///
+/// # Safety
+/// `num_formats` must be a valid pointer if non-null.
+pub unsafe fn get_number_of_formats(
+    num_formats: *mut u32,
+    formats: &[&dyn FormatRegistration],
+) -> HRESULT {
+    guard(format_args!("GetNumberOfFormats"), E_FAIL, || unsafe {
+        if num_formats.is_null() {
+            return E_INVALIDARG;
+        }
+        *num_formats = formats.len() as u32;
         S_OK
+    })
+}
+
+/// Implementation of GetHandlerProperty2 for the registered formats.
+///
+/// # Safety
+/// `value` must be a valid PROPVARIANT pointer if non-null.
+pub unsafe fn get_handler_property2(
+    format_index: u32,
+    prop_id: u32,
+    value: *mut c_void,
+    formats: &[&dyn FormatRegistration],
+) -> HRESULT {
+    guard(
+        format_args!(
+            "GetHandlerProperty2(format_index={}, prop_id={})",
+            format_index, prop_id
+        ),
+        E_FAIL,
+        || unsafe {
+            if value.is_null() {
+                return E_INVALIDARG;
+            }
+
+            let Some(format) = formats.get(format_index as usize) else {
+                return E_INVALIDARG;
+            };
+
+            let prop = &mut *(value as *mut RawPropVariant);
+            format.handler_property(prop_id, prop);
+
+            S_OK
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_are_space_separated() {
+        let exts = [
+            FormatExtension::new("tgzx").with_add_extension("tar"),
+            FormatExtension::new("gzx"),
+        ];
+        assert_eq!(join_extensions(&exts, |e| Some(e.ext)), "tgzx gzx");
+    }
+
+    #[test]
+    fn missing_add_extension_is_a_star() {
+        let exts = [
+            FormatExtension::new("tgzx").with_add_extension("tar"),
+            FormatExtension::new("gzx"),
+        ];
+        assert_eq!(join_extensions(&exts, |e| e.add_ext), "tar *");
+    }
+
+    #[test]
+    fn no_extensions() {
+        assert_eq!(join_extensions(&[], |e| Some(e.ext)), "");
+    }
+
+    #[test]
+    fn multi_signature_prefixes_lengths() {
+        let sigs: &[&[u8]] = &[b"PK\x03\x04", b"PK\x05\x06", b"\x1f"];
+        assert_eq!(
+            encode_multi_signature(sigs),
+            b"\x04PK\x03\x04\x04PK\x05\x06\x01\x1f".to_vec()
+        );
+    }
+
+    #[test]
+    fn multi_signature_empty() {
+        assert!(encode_multi_signature(&[]).is_empty());
+        assert_eq!(encode_multi_signature(&[b""]), vec![0]);
+    }
+
+    #[test]
+    fn multi_signature_keeps_255_bytes() {
+        let sig = [0xAB; 255];
+        let encoded = encode_multi_signature(&[&sig]);
+        assert_eq!(encoded.len(), 256);
+        assert_eq!(encoded[0], 255);
+        assert_eq!(&encoded[1..], &sig[..]);
     }
 }
@@ -1,21 +1,21 @@
 //! Generic COM handler wrapper that bridges safe traits to 7-Zip interfaces.
 
+use std::cell::{Cell, RefCell};
 use std::ffi::c_void;
+use std::fmt;
 use std::io::{Read, Seek, SeekFrom};
 use std::marker::PhantomData;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU32, Ordering};
 
-use windows::Win32::Foundation::{
-    E_INVALIDARG, E_NOINTERFACE, E_NOTIMPL, E_POINTER, S_FALSE, S_OK,
-};
-use windows::core::{BSTR, HRESULT};
-
+use crate::error::Error;
 use crate::traits::{ArchiveReader, ArchiveUpdater};
 
 use super::com::{
-    ArchivePropId,
     GUID,
     IArchiveExtractCallback,
+    IArchiveOpenCallback,
     IArchiveUpdateCallback,
     ICryptoGetTextPassword,
     ICryptoGetTextPassword2,
@@ -30,51 +30,28 @@ use super::com::{
     // Stream and callback wrapper types
     ISequentialInStream,
     ISequentialOutStream,
-    PropId,
 };
 
 // Import IUnknownVTable for vtable base field initialization
 use cppvtable::IUnknownVTable;
 
+use super::lock::{HandlerLock, HandlerLockGuard};
+use super::panic::{guard, guard_or_else, log_panic, panic_message};
 use super::propvariant::RawPropVariant;
-use crate::types::{PasswordProvider, PasswordRequester};
+use super::sys::{
+    BSTR, E_ABORT, E_FAIL, E_INVALIDARG, E_NOINTERFACE, E_NOTIMPL, E_OUTOFMEMORY, E_POINTER,
+    HRESULT, S_FALSE, S_OK,
+};
+use crate::types::{
+    ArchiveErrorFlags, ArchiveItem, ArchiveProperty, ExtractSinks, OpenContext, PasswordProvider,
+    PasswordRequester, PropId, PropValue, PropertyInfo,
+};
 
 // Stream seek origins
 const STREAM_SEEK_SET: u32 = 0;
 const STREAM_SEEK_CUR: u32 = 1;
 const STREAM_SEEK_END: u32 = 2;
 
-/// Read data from ISequentialInStream
-pub(crate) unsafe fn read_sequential_stream(stream: *mut c_void) -> std::io::Result<Vec<u8>> {
-    unsafe {
-        if stream.is_null() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Null stream pointer",
-            ));
-        }
-
-        let stream = ISequentialInStream::<c_void>::from_ptr_mut(stream);
-
-        let mut data = Vec::new();
-        let mut buffer = [0u8; 65536];
-
-        loop {
-            let mut bytes_read: u32 = 0;
-            let hr = stream.read(buffer.as_mut_ptr(), buffer.len() as u32, &mut bytes_read);
-            if hr.is_err() {
-                return Err(std::io::Error::other(format!("Read failed: {:?}", hr)));
-            }
-            if bytes_read == 0 {
-                break;
-            }
-            data.extend_from_slice(&buffer[..bytes_read as usize]);
-        }
-
-        Ok(data)
-    }
-}
-
 // =============================================================================
 // Streaming Input Reader
 // =============================================================================
@@ -187,6 +164,152 @@ impl Seek for InStreamReader {
     }
 }
 
+// =============================================================================
+// Lazy Update Item Stream
+// =============================================================================
+
+/// Reader for the data of a new item, backed by `IArchiveUpdateCallback::GetStream`.
+///
+/// The host stream is requested on the first read, so 7-Zip only opens a file
+/// when the updater actually reaches it. When dropped, the stream is released
+/// and the item's operation result is queued in `results`; `update_items`
+/// reports the queue once the whole update has succeeded. Items the updater
+/// never read are neither opened nor reported.
+struct UpdateItemStream<'a> {
+    callback: *mut c_void,
+    index: u32,
+    stream: *mut c_void,
+    opened: bool,
+    /// Operation result to report if opening or reading the stream failed
+    failure: Option<i32>,
+    /// Operation results of the items read so far
+    results: &'a RefCell<Vec<i32>>,
+    _callback: PhantomData<&'a IArchiveUpdateCallback<c_void>>,
+}
+
+impl<'a> UpdateItemStream<'a> {
+    /// Create a lazy stream for update item `index`.
+    ///
+    /// # Safety
+    /// `callback` must be a valid IArchiveUpdateCallback that outlives the reader.
+    unsafe fn new(callback: *mut c_void, index: u32, results: &'a RefCell<Vec<i32>>) -> Self {
+        Self {
+            callback,
+            index,
+            stream: std::ptr::null_mut(),
+            opened: false,
+            failure: None,
+            results,
+            _callback: PhantomData,
+        }
+    }
+
+    /// Ask 7-Zip for the item's input stream.
+    fn open(&mut self) -> std::io::Result<()> {
+        self.opened = true;
+
+        let hr = unsafe {
+            IArchiveUpdateCallback::<c_void>::from_ptr_mut(self.callback)
+                .get_stream(self.index, &mut self.stream)
+        };
+
+        if hr.is_err() {
+            self.stream = std::ptr::null_mut();
+            self.failure = Some(NRESULT_UNAVAILABLE);
+            return Err(std::io::Error::other(format!(
+                "GetStream failed with HRESULT: {:?}",
+                hr
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for UpdateItemStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.opened {
+            self.open()?;
+        }
+
+        // A null stream means the item has no data (e.g. an empty file)
+        if buf.is_empty() || self.stream.is_null() {
+            return Ok(0);
+        }
+
+        let chunk_size = buf.len().min(u32::MAX as usize) as u32;
+        let mut bytes_read: u32 = 0;
+
+        let hr = unsafe {
+            ISequentialInStream::<c_void>::from_ptr_mut(self.stream).read(
+                buf.as_mut_ptr(),
+                chunk_size,
+                &mut bytes_read,
+            )
+        };
+
+        if hr.is_err() {
+            self.failure = Some(NRESULT_DATA_ERROR);
+            return Err(std::io::Error::other(format!(
+                "Read failed with HRESULT: {:?}",
+                hr
+            )));
+        }
+
+        Ok(bytes_read as usize)
+    }
+}
+
+impl Drop for UpdateItemStream<'_> {
+    fn drop(&mut self) {
+        if !self.opened {
+            return;
+        }
+
+        if !self.stream.is_null() {
+            unsafe { ISequentialInStream::<c_void>::from_ptr_mut(self.stream).release() };
+            self.stream = std::ptr::null_mut();
+        }
+
+        self.results
+            .borrow_mut()
+            .push(self.failure.unwrap_or(NRESULT_OK));
+    }
+}
+
+/// Report the operation results queued by the `UpdateItemStream`s of a
+/// successful update, stopping at the first one 7-Zip rejects.
+fn report_update_results(
+    callback: &IArchiveUpdateCallback<c_void>,
+    results: &RefCell<Vec<i32>>,
+) -> HRESULT {
+    for result in results.take() {
+        let hr = unsafe { callback.set_operation_result(result) };
+        if hr.is_err() {
+            return hr;
+        }
+    }
+    S_OK
+}
+
+// =============================================================================
+// Error Mapping
+// =============================================================================
+
+/// Map a plugin error to the HRESULT returned to 7-Zip.
+///
+/// Cancellation always becomes `E_ABORT` so 7-Zip stops the operation, and
+/// errors with a natural COM equivalent get it; everything else maps to `fallback`.
+fn error_to_hresult(err: &Error, fallback: HRESULT) -> HRESULT {
+    match err.root() {
+        Error::Cancelled => E_ABORT,
+        Error::OutOfMemory => E_OUTOFMEMORY,
+        Error::NotSupported(_) | Error::UnsupportedMethod(_) => E_NOTIMPL,
+        Error::IndexOutOfBounds { .. } => E_INVALIDARG,
+        _ => fallback,
+    }
+}
+
 // =============================================================================
 // Generic Plugin Handler
 // =============================================================================
@@ -194,6 +317,15 @@ impl Seek for InStreamReader {
 /// Generic COM handler that wraps a safe archive implementation.
 ///
 /// This struct is the bridge between 7-Zip's COM interfaces and your safe Rust traits.
+///
+/// 7-Zip may call a handler from several threads (File Manager reads item
+/// properties on its UI thread while extraction runs on another). Those calls
+/// are serialized, so the plugin only ever sees one call at a time.
+///
+/// 7-Zip also reads item properties from inside the callbacks of `Extract`
+/// and `UpdateItems`, on the same thread, while the plugin is lent out as
+/// `&mut`. Those nested reads are answered from an `ItemSnapshot` taken
+/// before the plugin was lent out, and never touch `inner`.
 #[repr(C)]
 pub struct PluginHandler<T: ArchiveReader> {
     /// Pointer to IInArchive vtable - MUST be first field for COM compatibility
@@ -202,6 +334,8 @@ pub struct PluginHandler<T: ArchiveReader> {
     pub out_vtbl: *const IOutArchiveVTable<Self>,
     /// Reference count
     ref_count: AtomicU32,
+    /// Serializes calls from different threads
+    lock: Arc<HandlerLock>,
     /// The actual archive implementation (safe Rust)
     pub(crate) inner: T,
     /// Input stream pointer (AddRef'd, must Release on close)
@@ -210,6 +344,10 @@ pub struct PluginHandler<T: ArchiveReader> {
     pub(crate) archive_size: u64,
     /// Is archive open
     pub(crate) is_open: bool,
+    /// Why the last open failed (kpidErrorFlags), 0 if it didn't
+    pub(crate) error_flags: u32,
+    /// What nested calls may read while `Extract` or `UpdateItems` runs
+    snapshot: Option<ItemSnapshot>,
 }
 
 impl<T: ArchiveReader> PluginHandler<T> {
@@ -222,6 +360,157 @@ impl<T: ArchiveReader> PluginHandler<T> {
     pub fn inner_mut(&mut self) -> &mut T {
         &mut self.inner
     }
+
+    /// Release the input stream, if any.
+    fn release_stream(&mut self) {
+        if !self.in_stream.is_null() {
+            unsafe { IInStream::<c_void>::from_ptr_mut(self.in_stream).release() };
+            self.in_stream = std::ptr::null_mut();
+        }
+    }
+
+    /// Put the handler back in the closed state after plugin code panicked.
+    ///
+    /// The plugin may have been left half-updated, so it is replaced with a
+    /// fresh instance.
+    fn reset_after_panic(&mut self) {
+        self.snapshot = None;
+        self.release_stream();
+        self.is_open = false;
+        self.archive_size = 0;
+        self.error_flags = 0;
+
+        let this: *const Self = self;
+        guard(format_args!("PluginHandler reset({:p})", this), (), || {
+            let stale = std::mem::take(&mut self.inner);
+            drop(stale);
+        });
+    }
+}
+
+/// Enter the handler, waiting for calls on other threads to return.
+///
+/// # Safety
+/// `this` must point to a live handler.
+unsafe fn lock_handler<T: ArchiveReader>(this: *const PluginHandler<T>) -> HandlerLockGuard {
+    // The lock is shared through its own allocation, so holding it doesn't
+    // borrow the handler
+    unsafe { (*this).lock.lock() }
+}
+
+/// Item properties captured before the plugin is lent out as `&mut`.
+///
+/// 7-Zip reads the properties of the item it is writing (path, attributes,
+/// times) from inside the extract and update callbacks. The snapshot holds
+/// the standard and listed properties of the items involved, so those reads
+/// can be answered without a second reference to the plugin.
+struct ItemSnapshot {
+    count: usize,
+    /// Captured items in ascending index order, with their properties
+    items: Vec<(usize, Vec<(PropId, PropValue)>)>,
+}
+
+impl ItemSnapshot {
+    /// Capture the properties of `indices` (ascending, without duplicates).
+    fn capture<T: ArchiveReader>(inner: &T, indices: impl IntoIterator<Item = usize>) -> Self {
+        let mut ids: Vec<PropId> = PropertyInfo::STANDARD.iter().map(|info| info.id).collect();
+        for info in inner.item_properties() {
+            if !ids.contains(&info.id) {
+                ids.push(info.id);
+            }
+        }
+
+        let items = indices
+            .into_iter()
+            .map(|index| {
+                let properties = ids
+                    .iter()
+                    .filter_map(|&id| Some((id, inner.item_property(index, id)?)))
+                    .collect();
+                (index, properties)
+            })
+            .collect();
+
+        Self {
+            count: inner.item_count(),
+            items,
+        }
+    }
+
+    /// Property `id` of item `index`; `None` if the item wasn't captured.
+    fn property(&self, index: usize, id: PropId) -> Option<Option<&PropValue>> {
+        let pos = self
+            .items
+            .binary_search_by_key(&index, |&(index, _)| index)
+            .ok()?;
+        let properties = &self.items[pos].1;
+        Some(
+            properties
+                .iter()
+                .find(|(p, _)| *p == id)
+                .map(|(_, value)| value),
+        )
+    }
+}
+
+/// The handler as seen by an entry point that only reads it.
+enum HandlerView<'a, T: ArchiveReader> {
+    /// No call that changes the handler is running on this thread
+    Handler(&'a PluginHandler<T>),
+    /// Called back from inside such a call: the plugin is lent out, and only
+    /// the snapshot taken beforehand (if any) may be read
+    Nested(Option<&'a ItemSnapshot>),
+}
+
+/// Look at the handler from a read-only entry point holding `lock`.
+///
+/// # Safety
+/// `this` must point to a live handler, and `lock` must be its lock.
+unsafe fn view_handler<'a, T: ArchiveReader>(
+    this: *const PluginHandler<T>,
+    lock: &HandlerLockGuard,
+) -> HandlerView<'a, T> {
+    unsafe {
+        if lock.nested_in_exclusive() {
+            // Only borrow the snapshot field; `inner` is mutably borrowed
+            HandlerView::Nested((*this).snapshot.as_ref())
+        } else {
+            HandlerView::Handler(&*this)
+        }
+    }
+}
+
+/// Answer a nested read that can't be served from the snapshot.
+fn busy(call: &str) -> HRESULT {
+    crate::log_warn!("{} called while the handler is in use", call);
+    E_FAIL
+}
+
+/// Like [`guard`], for entry points that change the handler's state.
+///
+/// A call made from inside another call into the handler (e.g. `Close`
+/// from an extract callback) gets `E_FAIL` without running `f`, as the outer
+/// call still holds the plugin mutably.
+///
+/// After a panic the handler is reset to the closed state and 7-Zip gets
+/// `E_FAIL`. Other threads stay locked out until the reset is done.
+///
+/// # Safety
+/// `this` must point to a live handler that `f` no longer borrows once it
+/// returns.
+unsafe fn guard_handler<T: ArchiveReader>(
+    this: *mut PluginHandler<T>,
+    call: fmt::Arguments<'_>,
+    f: impl FnOnce() -> HRESULT,
+) -> HRESULT {
+    let Some(_lock) = (unsafe { (*this).lock.lock_exclusive() }) else {
+        crate::log_warn!("{} called while the handler is in use", call);
+        return E_FAIL;
+    };
+    guard_or_else(call, f, || {
+        unsafe { (*this).reset_after_panic() };
+        E_FAIL
+    })
 }
 
 // =============================================================================
@@ -233,39 +522,48 @@ unsafe extern "system" fn query_interface<T: ArchiveReader>(
     riid: *const GUID,
     ppv_object: *mut *mut c_void,
 ) -> HRESULT {
-    unsafe {
-        if ppv_object.is_null() {
-            return E_POINTER;
-        }
+    guard(
+        format_args!("IUnknown::QueryInterface({:p}, {:?})", this, unsafe {
+            riid.as_ref()
+        }),
+        E_FAIL,
+        || unsafe {
+            if ppv_object.is_null() {
+                return E_POINTER;
+            }
 
-        let riid = &*riid;
-        if *riid == IID_IUNKNOWN || *riid == IID_IINARCHIVE {
-            *ppv_object = this as *mut c_void;
-            add_ref(this);
-            return S_OK;
-        }
+            let riid = &*riid;
+            if *riid == IID_IUNKNOWN || *riid == IID_IINARCHIVE {
+                *ppv_object = this as *mut c_void;
+                add_ref(this);
+                return S_OK;
+            }
 
-        // Return IOutArchive interface if supported.
-        // COM requires returning a pointer to the location containing the vtable pointer.
-        // Since PluginHandler::out_vtbl is a `*const IOutArchiveVtbl`, we return the address
-        // of that field (`&handler.out_vtbl`). The IOutArchive wrapper functions then use
-        // `out_vtbl_to_handler()` to recover the base handler pointer.
-        if *riid == IID_IOUTARCHIVE && T::supports_write() {
-            let handler = &*this;
-            *ppv_object = &handler.out_vtbl as *const _ as *mut c_void;
-            add_ref(this);
-            return S_OK;
-        }
+            // Return IOutArchive interface if supported.
+            // COM requires returning a pointer to the location containing the vtable pointer.
+            // Since PluginHandler::out_vtbl is a `*const IOutArchiveVtbl`, we return the address
+            // of that field, taken with `&raw mut` as the handler is later written through it.
+            // The IOutArchive wrapper functions then use `out_vtbl_to_handler()` to recover the
+            // base handler pointer.
+            if *riid == IID_IOUTARCHIVE && T::supports_write() {
+                *ppv_object = &raw mut (*this).out_vtbl as *mut c_void;
+                add_ref(this);
+                return S_OK;
+            }
 
-        *ppv_object = std::ptr::null_mut();
-        E_NOINTERFACE
-    }
+            *ppv_object = std::ptr::null_mut();
+            E_NOINTERFACE
+        },
+    )
 }
 
 unsafe extern "system" fn add_ref<T: ArchiveReader>(this: *mut PluginHandler<T>) -> u32 {
     unsafe {
-        let handler = &*this;
-        handler.ref_count.fetch_add(1, Ordering::SeqCst) + 1
+        // Only touch the counter: 7-Zip may AddRef from inside a call that
+        // holds the plugin mutably
+        let count = (*this).ref_count.fetch_add(1, Ordering::SeqCst) + 1;
+        crate::log_trace!("IUnknown::AddRef({:p}) -> {}", this, count);
+        count
     }
 }
 
@@ -274,15 +572,19 @@ unsafe extern "system" fn release<T: ArchiveReader>(this: *mut PluginHandler<T>)
         // Avoid creating a reference before potential deallocation.
         // Access the atomic directly through the raw pointer.
         let count = (*this).ref_count.fetch_sub(1, Ordering::SeqCst) - 1;
+        crate::log_trace!("IUnknown::Release({:p}) -> {}", this, count);
         if count == 0 {
             // Release the input stream before destroying the handler
             let handler = &mut *this;
-            if !handler.in_stream.is_null() {
-                IInStream::<c_void>::from_ptr_mut(handler.in_stream).release();
-                handler.in_stream = std::ptr::null_mut();
-            }
-            handler.inner.close();
-            drop(Box::from_raw(this));
+            handler.release_stream();
+            guard(format_args!("ArchiveReader::close({:p})", this), (), || {
+                handler.inner.close()
+            });
+
+            // Free the handler even if the plugin's Drop panics
+            guard(format_args!("PluginHandler drop({:p})", this), (), || {
+                drop(Box::from_raw(this))
+            });
         }
         count
     }
@@ -333,8 +635,11 @@ impl PasswordRequester for PasswordRequesterWrapper {
             let mut password_ptr: *mut u16 = std::ptr::null_mut();
             let hr = crypto.crypto_get_text_password(&mut password_ptr);
 
+            if hr == E_ABORT {
+                return Err(Error::Cancelled); // User cancelled the prompt
+            }
             if hr.is_err() {
-                return Ok(None); // User cancelled or error
+                return Ok(None); // Password not available
             }
 
             if password_ptr.is_null() {
@@ -405,6 +710,9 @@ impl PasswordProvider for PasswordProviderWrapper {
             let mut password_ptr: *mut u16 = std::ptr::null_mut();
             let hr = crypto.crypto_get_text_password2(&mut password_is_defined, &mut password_ptr);
 
+            if hr == E_ABORT {
+                return Err(Error::Cancelled); // User cancelled the prompt
+            }
             if hr.is_err() {
                 return Ok(None); // Error getting password
             }
@@ -439,6 +747,87 @@ impl Drop for PasswordProviderWrapper {
     }
 }
 
+/// Wrapper around 7-Zip's IArchiveOpenCallback.
+///
+/// Forwards open progress to the host and records the first failed call, so
+/// `Open` can return its HRESULT. A null callback is accepted and simply
+/// reports nothing.
+struct OpenCallbackWrapper {
+    open_callback: *mut c_void,
+    host_error: Cell<Option<HRESULT>>,
+}
+
+impl OpenCallbackWrapper {
+    /// Wrap the open callback passed to `IInArchive::Open` (may be null).
+    fn new(open_callback: *mut c_void) -> Self {
+        Self {
+            open_callback,
+            host_error: Cell::new(None),
+        }
+    }
+
+    /// The HRESULT of the first progress call 7-Zip failed.
+    fn host_error(&self) -> Option<HRESULT> {
+        self.host_error.get()
+    }
+
+    /// Turn the HRESULT of a progress call into a plugin result.
+    ///
+    /// E_ABORT means the user cancelled; any other failure is a host error
+    /// that still stops the open.
+    fn check(&self, hr: HRESULT) -> crate::error::Result<()> {
+        if hr.is_ok() {
+            return Ok(());
+        }
+        if self.host_error.get().is_none() {
+            self.host_error.set(Some(hr));
+        }
+        if hr == E_ABORT {
+            Err(Error::Cancelled)
+        } else {
+            Err(Error::Other(format!("open callback failed: {:?}", hr)))
+        }
+    }
+}
+
+impl OpenContext for OpenCallbackWrapper {
+    fn set_total(&self, files: Option<u64>, bytes: Option<u64>) -> crate::error::Result<()> {
+        if self.open_callback.is_null() {
+            return Ok(());
+        }
+
+        let files_ptr = files.as_ref().map_or(std::ptr::null(), |f| f as *const u64);
+        let bytes_ptr = bytes.as_ref().map_or(std::ptr::null(), |b| b as *const u64);
+        let hr = unsafe {
+            IArchiveOpenCallback::<c_void>::from_ptr_mut(self.open_callback)
+                .set_total(files_ptr, bytes_ptr)
+        };
+        self.check(hr)
+    }
+
+    fn set_completed(&self, files: Option<u64>, bytes: Option<u64>) -> crate::error::Result<()> {
+        if self.open_callback.is_null() {
+            return Ok(());
+        }
+
+        let files_ptr = files.as_ref().map_or(std::ptr::null(), |f| f as *const u64);
+        let bytes_ptr = bytes.as_ref().map_or(std::ptr::null(), |b| b as *const u64);
+        let hr = unsafe {
+            IArchiveOpenCallback::<c_void>::from_ptr_mut(self.open_callback)
+                .set_completed(files_ptr, bytes_ptr)
+        };
+        self.check(hr)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        // SetCompleted(NULL, NULL) is how 7-Zip's own handlers poll for cancellation
+        if self.host_error.get().is_none() {
+            let _ = self.set_completed(None, None);
+        }
+        self.host_error.get() == Some(E_ABORT)
+    }
+}
+
 unsafe extern "system" fn open<T: ArchiveReader>(
     this: *mut PluginHandler<T>,
     stream: *mut c_void,
@@ -446,77 +835,95 @@ unsafe extern "system" fn open<T: ArchiveReader>(
     open_callback: *mut c_void,
 ) -> HRESULT {
     unsafe {
-        let handler = &mut *this;
-
-        if stream.is_null() {
-            return E_POINTER;
-        }
+        guard_handler(
+            this,
+            format_args!(
+                "IInArchive::Open({:p}, stream={:p}, callback={:p})",
+                this, stream, open_callback
+            ),
+            || {
+                (*this).error_flags = 0;
+
+                if stream.is_null() {
+                    return E_POINTER;
+                }
 
-        // Release any existing stream before opening a new one
-        // This can happen if 7-Zip reopens the archive after an update
-        if !handler.in_stream.is_null() {
-            IInStream::<c_void>::from_ptr_mut(handler.in_stream).release();
-            handler.in_stream = std::ptr::null_mut();
-        }
+                // Release any existing stream before opening a new one
+                // This can happen if 7-Zip reopens the archive after an update
+                (*this).release_stream();
+
+                // Create streaming reader wrapper
+                let mut reader = match InStreamReader::new(stream) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        crate::log_error!("Failed to create stream reader: {}", e);
+                        return S_FALSE;
+                    }
+                };
 
-        // Create streaming reader wrapper
-        let mut reader = match InStreamReader::new(stream) {
-            Ok(r) => r,
-            Err(_e) => {
-                #[cfg(debug_assertions)]
-                eprintln!("[sevenzip-plugin] Failed to create stream reader: {}", _e);
-                return S_FALSE;
-            }
-        };
+                let size = reader.size();
 
-        let size = reader.size();
+                // Try to get password requester from open callback
+                let password_requester = PasswordRequesterWrapper::try_from_callback(open_callback);
 
-        // Try to get password requester from open callback
-        let password_requester = PasswordRequesterWrapper::try_from_callback(open_callback);
+                // Progress reporting and cancellation through IArchiveOpenCallback
+                let open_context = OpenCallbackWrapper::new(open_callback);
 
-        // Call the safe streaming open method with password support
-        let open_result = if password_requester.is_some() {
-            handler.inner.open_with_password(
-                &mut reader,
-                size,
-                password_requester
-                    .as_ref()
-                    .map(|p| p as &dyn PasswordRequester),
-            )
-        } else {
-            handler.inner.open_with_password(&mut reader, size, None)
-        };
+                // Call the safe streaming open method with password support.
+                // Only `inner` is borrowed: the callbacks may call back into the handler.
+                let open_result = (*this).inner.open_with_password(
+                    &mut reader,
+                    size,
+                    &open_context,
+                    password_requester
+                        .as_ref()
+                        .map(|p| p as &dyn PasswordRequester),
+                );
 
-        if let Err(_e) = open_result {
-            #[cfg(debug_assertions)]
-            eprintln!("[sevenzip-plugin] Failed to open archive: {}", _e);
-            return S_FALSE;
-        }
+                if let Err(e) = open_result {
+                    crate::log_warn!("Failed to open archive: {}", e);
+                    if let Some(hr) = open_context.host_error() {
+                        return hr;
+                    }
+                    return match e.root() {
+                        Error::Cancelled => E_ABORT,
+                        Error::OutOfMemory => E_OUTOFMEMORY,
+                        _ => {
+                            // 7-Zip reads kpidErrorFlags after a failed open to decide
+                            // between "not this format" and "broken archive of this format"
+                            (*this).error_flags = open_error_flags(&e);
+                            S_FALSE
+                        }
+                    };
+                }
 
-        // AddRef the stream to keep it alive while we have it
-        IInStream::<c_void>::from_ptr_mut(stream).add_ref();
+                // AddRef the stream to keep it alive while we have it
+                IInStream::<c_void>::from_ptr_mut(stream).add_ref();
 
-        handler.in_stream = stream;
-        handler.archive_size = size;
-        handler.is_open = true;
-        S_OK
+                let handler = &mut *this;
+                handler.in_stream = stream;
+                handler.archive_size = size;
+                handler.is_open = true;
+                S_OK
+            },
+        )
     }
 }
 
 unsafe extern "system" fn close<T: ArchiveReader>(this: *mut PluginHandler<T>) -> HRESULT {
     unsafe {
-        let handler = &mut *this;
-        handler.inner.close();
+        guard_handler(this, format_args!("IInArchive::Close({:p})", this), || {
+            let handler = &mut *this;
+            handler.inner.close();
 
-        // Release the input stream if we have one
-        if !handler.in_stream.is_null() {
-            IInStream::<c_void>::from_ptr_mut(handler.in_stream).release();
-            handler.in_stream = std::ptr::null_mut();
-        }
+            // Release the input stream if we have one
+            handler.release_stream();
 
-        handler.is_open = false;
-        handler.archive_size = 0;
-        S_OK
+            handler.is_open = false;
+            handler.archive_size = 0;
+            handler.error_flags = 0;
+            S_OK
+        })
     }
 }
 
@@ -524,14 +931,22 @@ unsafe extern "system" fn get_number_of_items<T: ArchiveReader>(
     this: *mut PluginHandler<T>,
     num_items: *mut u32,
 ) -> HRESULT {
-    unsafe {
-        if num_items.is_null() {
-            return E_INVALIDARG;
-        }
-        let handler = &*this;
-        *num_items = handler.inner.item_count() as u32;
-        S_OK
-    }
+    guard(
+        format_args!("IInArchive::GetNumberOfItems({:p})", this),
+        E_FAIL,
+        || unsafe {
+            let lock = lock_handler(this);
+            if num_items.is_null() {
+                return E_INVALIDARG;
+            }
+            *num_items = match view_handler(this, &lock) {
+                HandlerView::Handler(handler) => handler.inner.item_count(),
+                HandlerView::Nested(Some(snapshot)) => snapshot.count,
+                HandlerView::Nested(None) => return busy("IInArchive::GetNumberOfItems"),
+            } as u32;
+            S_OK
+        },
+    )
 }
 
 unsafe extern "system" fn get_property<T: ArchiveReader>(
@@ -540,90 +955,129 @@ unsafe extern "system" fn get_property<T: ArchiveReader>(
     prop_id: u32,
     value: *mut c_void,
 ) -> HRESULT {
-    unsafe {
-        if value.is_null() {
-            return E_INVALIDARG;
-        }
-
-        let handler = &*this;
-        let Some(item) = handler.inner.get_item(index as usize) else {
-            return E_INVALIDARG;
-        };
-
-        let prop = &mut *(value as *mut RawPropVariant);
-
-        match prop_id {
-            x if x == PropId::Path as u32 => {
-                prop.set_bstr(&item.name);
-            }
-            x if x == PropId::Size as u32 => {
-                prop.set_u64(item.size);
-            }
-            x if x == PropId::PackSize as u32 => {
-                if let Some(packed) = item.compressed_size {
-                    prop.set_u64(packed);
-                } else {
-                    prop.set_empty();
-                }
-            }
-            x if x == PropId::IsDir as u32 => {
-                prop.set_bool(item.is_dir);
+    guard(
+        format_args!(
+            "IInArchive::GetProperty({:p}, index={}, prop_id={})",
+            this, index, prop_id
+        ),
+        E_FAIL,
+        || unsafe {
+            let lock = lock_handler(this);
+            if value.is_null() {
+                return E_INVALIDARG;
             }
-            x if x == PropId::MTime as u32 => {
-                if let Some(mtime) = item.modified {
-                    prop.set_filetime(super::propvariant::systemtime_to_filetime(mtime));
-                } else {
-                    prop.set_empty();
-                }
-            }
-            x if x == PropId::CTime as u32 => {
-                if let Some(ctime) = item.created {
-                    prop.set_filetime(super::propvariant::systemtime_to_filetime(ctime));
-                } else {
-                    prop.set_empty();
-                }
-            }
-            x if x == PropId::ATime as u32 => {
-                if let Some(atime) = item.accessed {
-                    prop.set_filetime(super::propvariant::systemtime_to_filetime(atime));
-                } else {
-                    prop.set_empty();
-                }
-            }
-            x if x == PropId::Attrib as u32 => {
-                if let Some(attrs) = item.attributes {
-                    prop.set_u32(attrs);
-                } else {
-                    prop.set_empty();
+
+            let index = index as usize;
+            let id = PropId(prop_id);
+            let property = match view_handler(this, &lock) {
+                HandlerView::Handler(handler) => {
+                    if index >= handler.inner.item_count() {
+                        return E_INVALIDARG;
+                    }
+                    let property = handler.inner.item_property(index, id);
+                    if id == PropId::PATH && property.is_none() {
+                        warn_if_no_properties(&handler.inner, index);
+                    }
+                    property
                 }
-            }
-            x if x == PropId::Crc as u32 => {
-                if let Some(crc) = item.crc {
-                    prop.set_u32(crc);
-                } else {
-                    prop.set_empty();
+                HandlerView::Nested(Some(snapshot)) => {
+                    if index >= snapshot.count {
+                        return E_INVALIDARG;
+                    }
+                    match snapshot.property(index, id) {
+                        Some(property) => property.cloned(),
+                        None => return busy("IInArchive::GetProperty"),
+                    }
                 }
+                HandlerView::Nested(None) => return busy("IInArchive::GetProperty"),
+            };
+
+            let prop = &mut *(value as *mut RawPropVariant);
+
+            match property {
+                Some(PropValue::FileTime(time)) => prop.set_time(time, T::time_precision()),
+                Some(value) => prop.set_value(&value),
+                None => prop.set_empty(),
             }
-            x if x == PropId::Encrypted as u32 => {
-                prop.set_bool(item.encrypted);
-            }
-            _ => {
-                prop.set_empty();
-            }
-        }
 
-        S_OK
+            S_OK
+        },
+    )
+}
+
+/// Warn about an item without a path that looks like the format implements
+/// neither `get_item()` nor `item_property()`.
+fn warn_if_no_properties<T: ArchiveReader>(inner: &T, index: usize) {
+    if inner.get_item(index).is_none() && inner.item_property(index, PropId::SIZE).is_none() {
+        crate::log_warn!(
+            "item {} has no path or size: implement get_item() or item_property()",
+            index
+        );
     }
 }
 
-// Extract mode and result constants
+/// Size of item `index` as reported by the plugin, for progress (0 if unknown).
+fn item_size<T: ArchiveReader>(inner: &T, index: usize) -> u64 {
+    inner
+        .item_property(index, PropId::SIZE)
+        .and_then(|size| size.as_u64())
+        .unwrap_or(0)
+}
+
+// Extract mode and result constants (NArchive::NExtract)
 const NASK_EXTRACT: i32 = 0;
+const NASK_TEST: i32 = 1;
 const NRESULT_OK: i32 = 0;
-const NRESULT_DATA_ERROR: i32 = 1;
-
-/// Wrapper for ISequentialOutStream that implements `std::io::Write`.
+const NRESULT_UNSUPPORTED_METHOD: i32 = 1;
+const NRESULT_DATA_ERROR: i32 = 2;
+const NRESULT_CRC_ERROR: i32 = 3;
+const NRESULT_UNAVAILABLE: i32 = 4;
+const NRESULT_UNEXPECTED_END: i32 = 5;
+const NRESULT_DATA_AFTER_END: i32 = 6;
+const NRESULT_IS_NOT_ARC: i32 = 7;
+const NRESULT_WRONG_PASSWORD: i32 = 9;
+
+/// Map a failed extraction or test to 7-Zip's operation result.
 ///
-/// This allows streaming writes directly to 7-Zip's output stream,
+/// 7-Zip shows each result differently (e.g. "Wrong password?" instead of
+/// "Data error"); anything without a dedicated result is a data error.
+fn operation_result(err: &Error) -> i32 {
+    match err.root() {
+        Error::UnsupportedMethod(_) | Error::NotSupported(_) => NRESULT_UNSUPPORTED_METHOD,
+        Error::ChecksumMismatch => NRESULT_CRC_ERROR,
+        Error::Unavailable => NRESULT_UNAVAILABLE,
+        Error::UnexpectedEof => NRESULT_UNEXPECTED_END,
+        Error::DataAfterEnd => NRESULT_DATA_AFTER_END,
+        Error::NotArchive => NRESULT_IS_NOT_ARC,
+        Error::WrongPassword => NRESULT_WRONG_PASSWORD,
+        _ => NRESULT_DATA_ERROR,
+    }
+}
+
+/// kpv_ErrorFlags_IsNotArc - the only flag plugins don't set themselves.
+const ERROR_FLAGS_IS_NOT_ARC: u32 = 1 << 0;
+
+/// Map a failed open to 7-Zip's archive error flags.
+///
+/// Any flag other than "is not archive" tells 7-Zip the file is in this
+/// format but damaged, so it reports the problem instead of trying other
+/// handlers. Unclassified errors keep the old "not this format" behaviour.
+fn open_error_flags(err: &Error) -> u32 {
+    let flags = match err.root() {
+        Error::InvalidFormat(_) => ArchiveErrorFlags::HEADERS_ERROR,
+        Error::WrongPassword => ArchiveErrorFlags::ENCRYPTED_HEADERS_ERROR,
+        Error::UnexpectedEof => ArchiveErrorFlags::UNEXPECTED_END,
+        Error::UnsupportedMethod(_) => ArchiveErrorFlags::UNSUPPORTED_METHOD,
+        Error::NotSupported(_) => ArchiveErrorFlags::UNSUPPORTED_FEATURE,
+        Error::ChecksumMismatch => ArchiveErrorFlags::CRC_ERROR,
+        _ => return ERROR_FLAGS_IS_NOT_ARC,
+    };
+    flags.bits()
+}
+
+/// Wrapper for ISequentialOutStream that implements `std::io::Write`.
+///
+/// This allows streaming writes directly to 7-Zip's output stream,
 /// avoiding intermediate allocations.
 struct SeqOutStreamWriter {
     stream: *mut c_void,
@@ -680,259 +1134,656 @@ impl std::io::Write for SeqOutStreamWriter {
     }
 }
 
-unsafe extern "system" fn extract<T: ArchiveReader>(
-    this: *mut PluginHandler<T>,
-    indices: *const u32,
-    num_items: u32,
-    test_mode: i32,
-    extract_callback: *mut c_void,
-) -> HRESULT {
-    unsafe {
-        let handler = &mut *this;
+/// Minimum number of bytes between two progress reports while extracting an item.
+const EXTRACT_PROGRESS_STEP: u64 = 1 << 20;
+
+/// Writer adapter that forwards byte-level progress to `IArchiveExtractCallback`.
+///
+/// Every `EXTRACT_PROGRESS_STEP` bytes it reports `base + written` through
+/// `SetCompleted`. If 7-Zip rejects the update (E_ABORT when the user presses
+/// Cancel), further writes fail and `host_error()` returns the HRESULT.
+struct ExtractProgressWriter<'a, W: std::io::Write> {
+    inner: W,
+    callback: &'a IArchiveExtractCallback<c_void>,
+    base: u64,
+    written: u64,
+    reported: u64,
+    host_error: Option<HRESULT>,
+}
 
-        if extract_callback.is_null() {
-            return E_INVALIDARG;
+impl<'a, W: std::io::Write> ExtractProgressWriter<'a, W> {
+    /// Wrap `inner`, reporting progress relative to `base` completed bytes.
+    fn new(inner: W, callback: &'a IArchiveExtractCallback<c_void>, base: u64) -> Self {
+        Self {
+            inner,
+            callback,
+            base,
+            written: 0,
+            reported: 0,
+            host_error: None,
         }
+    }
 
-        // Get callback wrapper for type-safe method calls
-        let callback = IArchiveExtractCallback::<c_void>::from_ptr_mut(extract_callback);
+    /// The failed `SetCompleted` result, if 7-Zip rejected a progress update.
+    fn host_error(&self) -> Option<HRESULT> {
+        self.host_error
+    }
+
+    fn report(&mut self) {
+        self.reported = self.written;
+        let completed = self.base + self.written;
+        let hr = unsafe { self.callback.set_completed(&completed) };
+        if hr.is_err() {
+            self.host_error = Some(hr);
+        }
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for ExtractProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.host_error.is_some() {
+            // Converts back to Error::Cancelled if the plugin propagates it with `?`
+            return Err(std::io::Error::other(Error::Cancelled));
+        }
+
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+
+        if self.written - self.reported >= EXTRACT_PROGRESS_STEP {
+            self.report();
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An item opened through [`ExtractCallbackSinks`], holding its output stream.
+struct OpenExtractItem<'a> {
+    index: usize,
+    out_stream: *mut c_void,
+    /// `None` when 7-Zip skips the item
+    writer: Option<ExtractProgressWriter<'a, Box<dyn std::io::Write>>>,
+}
+
+impl Drop for OpenExtractItem<'_> {
+    fn drop(&mut self) {
+        if !self.out_stream.is_null() {
+            unsafe { ISequentialOutStream::<c_void>::from_ptr_mut(self.out_stream).release() };
+        }
+    }
+}
+
+/// `ExtractSinks` backed by 7-Zip's IArchiveExtractCallback.
+///
+/// Each item goes through GetStream, PrepareOperation and SetOperationResult,
+/// and overall progress advances by the item's size once it is finished.
+/// If a callback fails (E_ABORT when the user presses Cancel), the plugin gets
+/// `Error::Cancelled` and the HRESULT is kept for `extract` to return.
+///
+/// GetStream and SetOperationResult may call back into the handler while the
+/// plugin is still lent out to `extract_many()`; `extract` sets up the handler's
+/// [`ItemSnapshot`] before creating the sinks so those calls have something to read.
+struct ExtractCallbackSinks<'a> {
+    callback: &'a IArchiveExtractCallback<c_void>,
+    test_mode: bool,
+    /// Requested indices in ascending order, with their sizes
+    indices: &'a [usize],
+    sizes: Vec<u64>,
+    finished: Vec<bool>,
+    completed: u64,
+    current: Option<OpenExtractItem<'a>>,
+    host_error: Option<HRESULT>,
+}
+
+impl<'a> ExtractCallbackSinks<'a> {
+    fn new(
+        callback: &'a IArchiveExtractCallback<c_void>,
+        test_mode: bool,
+        indices: &'a [usize],
+        sizes: Vec<u64>,
+    ) -> Self {
+        let finished = vec![false; indices.len()];
+        Self {
+            callback,
+            test_mode,
+            indices,
+            sizes,
+            finished,
+            completed: 0,
+            current: None,
+            host_error: None,
+        }
+    }
+
+    /// Remember a failed callback; the plugin is told to stop.
+    fn fail(&mut self, hr: HRESULT) -> Error {
+        self.host_error = Some(hr);
+        Error::Cancelled
+    }
+
+    /// Position of `index` in `indices` if it was requested and isn't finished.
+    fn pending(&self, index: usize) -> Option<usize> {
+        self.indices
+            .binary_search(&index)
+            .ok()
+            .filter(|&pos| !self.finished[pos])
+    }
+
+    /// Report a requested item the plugin never finished.
+    ///
+    /// 7-Zip expects every result to follow a GetStream for the same item, so
+    /// the item is opened first unless it already is.
+    fn abandon(&mut self, index: usize, error: Error) -> crate::error::Result<()> {
+        if self.current.as_ref().is_none_or(|item| item.index != index) {
+            self.open(index)?;
+        }
+        self.finish(index, Err(error))
+    }
+
+    /// Requested items that have not been finished, the open one first.
+    fn unfinished(&self) -> Vec<usize> {
+        let current = self.current.as_ref().map(|item| item.index);
+        let rest = self
+            .indices
+            .iter()
+            .zip(&self.finished)
+            .filter(|&(&index, &finished)| !finished && Some(index) != current)
+            .map(|(&index, _)| index);
+        current.into_iter().chain(rest).collect()
+    }
+}
+
+impl ExtractSinks for ExtractCallbackSinks<'_> {
+    fn is_test(&self) -> bool {
+        self.test_mode
+    }
+
+    fn open(&mut self, index: usize) -> crate::error::Result<Option<&mut dyn std::io::Write>> {
+        if self.host_error.is_some() {
+            return Err(Error::Cancelled);
+        }
 
-        // Try to get password requester from extract callback
-        // (for formats like ZIP where individual files can be encrypted)
-        let password_requester = PasswordRequesterWrapper::try_from_callback(extract_callback);
+        // Each requested item is opened once
+        let current = self.current.as_ref().map(|item| item.index);
+        if self.pending(index).is_none() || current == Some(index) {
+            return Err(Error::Other(format!(
+                "item {} was not requested or was already opened",
+                index
+            )));
+        }
 
-        // Determine which indices to extract
-        let extract_all = num_items == u32::MAX;
-        let indices_to_extract: Vec<usize> = if extract_all {
-            (0..handler.inner.item_count()).collect()
+        // An item left open without a result did not complete
+        if let Some(previous) = current {
+            self.finish(previous, Err(Error::Unavailable))?;
+        }
+
+        // In test mode 7-Zip provides no output stream; the data is decoded and verified
+        let ask_mode = if self.test_mode {
+            NASK_TEST
         } else {
-            std::slice::from_raw_parts(indices, num_items as usize)
-                .iter()
-                .map(|&i| i as usize)
-                .collect()
+            NASK_EXTRACT
         };
 
-        // Calculate total size
-        let total_size: u64 = indices_to_extract
-            .iter()
-            .filter_map(|&i| handler.inner.get_item(i))
-            .map(|item| item.size)
-            .sum();
+        let mut out_stream: *mut c_void = std::ptr::null_mut();
+        let hr = unsafe {
+            self.callback
+                .get_stream(index as u32, &mut out_stream, ask_mode)
+        };
+        if hr.is_err() {
+            return Err(self.fail(hr));
+        }
 
-        let _ = callback.set_total(total_size);
+        let mut item = OpenExtractItem {
+            index,
+            out_stream,
+            writer: None,
+        };
 
-        let mut completed: u64 = 0;
+        let hr = unsafe { self.callback.prepare_operation(ask_mode) };
+        if hr.is_err() {
+            return Err(self.fail(hr));
+        }
 
-        for &index in &indices_to_extract {
-            // Get item size before mutable borrow for extract()
-            let item_size = match handler.inner.get_item(index) {
-                Some(item) => item.size,
-                None => continue,
-            };
+        // Write through a progress reporter, into a discarding sink when testing.
+        // No stream in extract mode means 7-Zip asked to skip this item.
+        let output: Option<Box<dyn std::io::Write>> = if self.test_mode {
+            Some(Box::new(std::io::sink()))
+        } else if !out_stream.is_null() {
+            Some(Box::new(SeqOutStreamWriter::new(out_stream)))
+        } else {
+            None
+        };
+        item.writer =
+            output.map(|output| ExtractProgressWriter::new(output, self.callback, self.completed));
+
+        let item = self.current.insert(item);
+        Ok(item
+            .writer
+            .as_mut()
+            .map(|writer| writer as &mut dyn std::io::Write))
+    }
 
-            // Get output stream
-            let mut out_stream: *mut c_void = std::ptr::null_mut();
-            let hr = callback.get_stream(index as u32, &mut out_stream, NASK_EXTRACT);
-            if hr.is_err() {
-                return hr;
-            }
+    fn finish(
+        &mut self,
+        index: usize,
+        result: crate::error::Result<()>,
+    ) -> crate::error::Result<()> {
+        if self.host_error.is_some() {
+            return Err(Error::Cancelled);
+        }
 
-            // Prepare operation
-            let _ = callback.prepare_operation(NASK_EXTRACT);
+        // Only the open item can be finished; anything else would report a
+        // second result, or one without a GetStream
+        let Some(item) = self.current.take_if(|item| item.index == index) else {
+            return Err(Error::Other(format!("item {} is not open", index)));
+        };
 
-            // If test mode or no stream, skip extraction
-            let result = if test_mode != 0 || out_stream.is_null() {
-                NRESULT_OK
-            } else {
-                // Extract data using streaming trait method with password support
-                let mut writer = SeqOutStreamWriter::new(out_stream);
+        // A write rejected by 7-Zip surfaces as whatever error the plugin
+        // returned - stop with the HRESULT 7-Zip gave instead
+        let host_error = item.writer.as_ref().and_then(|w| w.host_error());
 
-                let extract_result = handler.inner.extract_to_with_password(
-                    index,
-                    &mut writer,
-                    password_requester
-                        .as_ref()
-                        .map(|p| p as &dyn PasswordRequester),
-                );
+        // Release the output stream before reporting the result
+        drop(item);
+        if let Ok(pos) = self.indices.binary_search(&index) {
+            self.finished[pos] = true;
+            self.completed += self.sizes[pos];
+        }
 
-                match extract_result {
-                    Ok(_) => NRESULT_OK,
-                    Err(_) => NRESULT_DATA_ERROR,
-                }
-            };
+        if let Some(hr) = host_error {
+            return Err(self.fail(hr));
+        }
 
-            // Release output stream
-            if !out_stream.is_null() {
-                ISequentialOutStream::<c_void>::from_ptr_mut(out_stream).release();
-            }
+        // The user cancelled (e.g. at a password prompt) - stop extracting
+        if let Err(e) = &result
+            && matches!(e.root(), Error::Cancelled)
+        {
+            return Err(self.fail(E_ABORT));
+        }
 
-            // Set operation result
-            let hr = callback.set_operation_result(result);
-            if hr.is_err() {
-                return hr;
-            }
+        let op_result = match &result {
+            Ok(()) => NRESULT_OK,
+            Err(e) => operation_result(e),
+        };
+        let hr = unsafe { self.callback.set_operation_result(op_result) };
+        if hr.is_err() {
+            return Err(self.fail(hr));
+        }
 
-            // Update progress
-            completed += item_size;
-            let _ = callback.set_completed(&completed);
+        // Update progress - 7-Zip returns E_ABORT here when the user presses Cancel
+        let hr = unsafe { self.callback.set_completed(&self.completed) };
+        if hr.is_err() {
+            return Err(self.fail(hr));
         }
 
-        S_OK
+        Ok(())
     }
 }
 
-unsafe extern "system" fn get_archive_property<T: ArchiveReader>(
+unsafe extern "system" fn extract<T: ArchiveReader>(
     this: *mut PluginHandler<T>,
-    prop_id: u32,
-    value: *mut c_void,
+    indices: *const u32,
+    num_items: u32,
+    test_mode: i32,
+    extract_callback: *mut c_void,
 ) -> HRESULT {
     unsafe {
-        if value.is_null() {
-            return E_INVALIDARG;
-        }
+        guard_handler(
+            this,
+            format_args!(
+                "IInArchive::Extract({:p}, num_items={}, test_mode={}, callback={:p})",
+                this, num_items, test_mode, extract_callback
+            ),
+            || {
+                if extract_callback.is_null() {
+                    return E_INVALIDARG;
+                }
 
-        let handler = &*this;
-        let prop = &mut *(value as *mut RawPropVariant);
+                // Get callback wrapper for type-safe method calls
+                let callback = IArchiveExtractCallback::<c_void>::from_ptr_mut(extract_callback);
 
-        match prop_id {
-            x if x == ArchivePropId::PhySize as u32 => {
-                if let Some(size) = handler.inner.physical_size() {
-                    prop.set_u64(size);
+                // Try to get password requester from extract callback
+                // (for formats like ZIP where individual files can be encrypted)
+                let password_requester =
+                    PasswordRequesterWrapper::try_from_callback(extract_callback);
+
+                // Determine which indices to extract
+                let extract_all = num_items == u32::MAX;
+                let mut requested: Vec<usize> = if extract_all {
+                    (0..(*this).inner.item_count()).collect()
                 } else {
-                    prop.set_u64(handler.archive_size);
+                    std::slice::from_raw_parts(indices, num_items as usize)
+                        .iter()
+                        .map(|&i| i as usize)
+                        .collect()
+                };
+                requested.sort_unstable();
+                requested.dedup();
+
+                // Unknown indices are skipped; the rest are handed to the plugin in one batch
+                let item_count = (*this).inner.item_count();
+                let indices_to_extract: Vec<usize> = requested
+                    .into_iter()
+                    .filter(|&index| index < item_count)
+                    .collect();
+                let sizes: Vec<u64> = indices_to_extract
+                    .iter()
+                    .map(|&index| item_size(&(*this).inner, index))
+                    .collect();
+
+                // Calculate total size
+                let total_size: u64 = sizes.iter().sum();
+
+                // Nested GetProperty calls read the requested items from here
+                let snapshot = ItemSnapshot::capture(&(*this).inner, indices_to_extract.clone());
+                (*this).snapshot = Some(snapshot);
+
+                let hr = callback.set_total(total_size);
+                if hr.is_err() {
+                    (*this).snapshot = None;
+                    return hr;
+                }
+
+                let mut sinks =
+                    ExtractCallbackSinks::new(callback, test_mode != 0, &indices_to_extract, sizes);
+
+                // A panic while decoding fails the unfinished items with a data error,
+                // then closes the archive
+                // 7-Zip calls back into the handler while the plugin extracts,
+                // so only `inner` is borrowed mutably, and only for the plugin call
+                let extracted = catch_unwind(AssertUnwindSafe(|| {
+                    (*this).inner.extract_many(
+                        &indices_to_extract,
+                        &mut sinks,
+                        password_requester
+                            .as_ref()
+                            .map(|p| p as &dyn PasswordRequester),
+                    )
+                }));
+                let (result, panicked) = match extracted {
+                    Ok(result) => (result, false),
+                    Err(payload) => {
+                        log_panic("IInArchive::Extract", payload.as_ref());
+                        let message =
+                            format!("plugin panicked: {}", panic_message(payload.as_ref()));
+                        (Err(Error::Other(message)), true)
+                    }
+                };
+
+                let hr = finish_extract(&mut sinks, result);
+                (*this).snapshot = None;
+                if panicked {
+                    (*this).reset_after_panic();
                 }
+                hr
+            },
+        )
+    }
+}
+
+/// Turn the outcome of `extract_many()` into the HRESULT returned to 7-Zip,
+/// reporting the requested items the plugin never finished.
+fn finish_extract(
+    sinks: &mut ExtractCallbackSinks<'_>,
+    result: crate::error::Result<()>,
+) -> HRESULT {
+    if let Some(hr) = sinks.host_error {
+        return hr;
+    }
+    if let Err(e) = &result
+        && matches!(e.root(), Error::Cancelled)
+    {
+        return E_ABORT;
+    }
+
+    // Items the plugin never finished get the error it returned
+    let unfinished = sinks.unfinished();
+    let error = match result {
+        Err(e) if unfinished.is_empty() => return error_to_hresult(&e, E_FAIL),
+        Err(e) => e,
+        Ok(()) => Error::Unavailable,
+    };
+    for index in unfinished {
+        if sinks.abandon(index, error.clone()).is_err() {
+            return sinks.host_error.unwrap_or(E_ABORT);
+        }
+    }
+
+    S_OK
+}
+
+unsafe extern "system" fn get_archive_property<T: ArchiveReader>(
+    this: *mut PluginHandler<T>,
+    prop_id: u32,
+    value: *mut c_void,
+) -> HRESULT {
+    guard(
+        format_args!(
+            "IInArchive::GetArchiveProperty({:p}, prop_id={})",
+            this, prop_id
+        ),
+        E_FAIL,
+        || unsafe {
+            let lock = lock_handler(this);
+            if value.is_null() {
+                return E_INVALIDARG;
             }
-            _ => {
-                prop.set_empty();
+
+            let HandlerView::Handler(handler) = view_handler(this, &lock) else {
+                return busy("IInArchive::GetArchiveProperty");
+            };
+            let prop = &mut *(value as *mut RawPropVariant);
+
+            match PropId(prop_id) {
+                PropId::PHY_SIZE => {
+                    if let Some(size) = handler.inner.physical_size() {
+                        prop.set_u64(size);
+                    } else {
+                        prop.set_u64(handler.archive_size);
+                    }
+                }
+                PropId::ERROR_FLAGS => {
+                    // After a failed open, report why; otherwise ask the plugin
+                    let flags = if handler.is_open {
+                        handler.inner.diagnostics().errors.bits()
+                    } else {
+                        handler.error_flags
+                    };
+                    if flags != 0 {
+                        prop.set_u32(flags);
+                    } else {
+                        prop.set_empty();
+                    }
+                }
+                PropId::WARNING_FLAGS if handler.is_open => {
+                    let flags = handler.inner.diagnostics().warnings;
+                    if !flags.is_empty() {
+                        prop.set_u32(flags.bits());
+                    } else {
+                        prop.set_empty();
+                    }
+                }
+                PropId::ERROR if handler.is_open => match handler.inner.diagnostics().error {
+                    Some(message) => prop.set_bstr(&message),
+                    None => prop.set_empty(),
+                },
+                PropId::WARNING if handler.is_open => match handler.inner.diagnostics().warning {
+                    Some(message) => prop.set_bstr(&message),
+                    None => prop.set_empty(),
+                },
+                id if handler.is_open => {
+                    match handler
+                        .inner
+                        .archive_properties()
+                        .into_iter()
+                        .find(|p| p.id() == id)
+                    {
+                        Some(p) => prop.set_value(&p.value()),
+                        None => prop.set_empty(),
+                    }
+                }
+                _ => {
+                    prop.set_empty();
+                }
             }
-        }
 
-        S_OK
-    }
+            S_OK
+        },
+    )
 }
 
 unsafe extern "system" fn get_number_of_properties<T: ArchiveReader>(
-    _this: *mut PluginHandler<T>,
+    this: *mut PluginHandler<T>,
     num_props: *mut u32,
 ) -> HRESULT {
-    unsafe {
-        if !num_props.is_null() {
-            *num_props = 10; // Path, Size, PackSize, IsDir, MTime, CTime, ATime, Attrib, CRC, Encrypted
-        }
-        S_OK
-    }
+    guard(
+        format_args!("IInArchive::GetNumberOfProperties({:p})", this),
+        E_FAIL,
+        || unsafe {
+            let lock = lock_handler(this);
+            let HandlerView::Handler(handler) = view_handler(this, &lock) else {
+                return busy("IInArchive::GetNumberOfProperties");
+            };
+            if !num_props.is_null() {
+                *num_props = handler.inner.item_properties().len() as u32;
+            }
+            S_OK
+        },
+    )
 }
 
 unsafe extern "system" fn get_property_info<T: ArchiveReader>(
-    _this: *mut PluginHandler<T>,
+    this: *mut PluginHandler<T>,
     index: u32,
     name: *mut c_void,
     prop_id: *mut u32,
     var_type: *mut u32,
 ) -> HRESULT {
-    unsafe {
-        use super::propvariant::{VT_BOOL, VT_BSTR, VT_FILETIME, VT_UI4, VT_UI8};
+    guard(
+        format_args!("IInArchive::GetPropertyInfo({:p}, index={})", this, index),
+        E_FAIL,
+        || unsafe {
+            let lock = lock_handler(this);
+            if name.is_null() || prop_id.is_null() || var_type.is_null() {
+                return E_INVALIDARG;
+            }
 
-        if name.is_null() || prop_id.is_null() || var_type.is_null() {
-            return E_INVALIDARG;
-        }
+            let HandlerView::Handler(handler) = view_handler(this, &lock) else {
+                return busy("IInArchive::GetPropertyInfo");
+            };
+            let Some(info) = handler
+                .inner
+                .item_properties()
+                .into_iter()
+                .nth(index as usize)
+            else {
+                return E_INVALIDARG;
+            };
 
-        *(name as *mut BSTR) = BSTR::default();
+            // 7-Zip only needs a name for properties it doesn't know by ID
+            *(name as *mut BSTR) = match &info.name {
+                Some(column) => BSTR::from(column.as_ref()),
+                None => BSTR::default(),
+            };
+            *prop_id = info.id.0;
+            *var_type = super::propvariant::var_type(info.prop_type) as u32;
 
-        match index {
-            0 => {
-                *prop_id = PropId::Path as u32;
-                *var_type = VT_BSTR as u32;
-            }
-            1 => {
-                *prop_id = PropId::Size as u32;
-                *var_type = VT_UI8 as u32;
-            }
-            2 => {
-                *prop_id = PropId::PackSize as u32;
-                *var_type = VT_UI8 as u32;
-            }
-            3 => {
-                *prop_id = PropId::IsDir as u32;
-                *var_type = VT_BOOL as u32;
-            }
-            4 => {
-                *prop_id = PropId::MTime as u32;
-                *var_type = VT_FILETIME as u32;
-            }
-            5 => {
-                *prop_id = PropId::CTime as u32;
-                *var_type = VT_FILETIME as u32;
-            }
-            6 => {
-                *prop_id = PropId::ATime as u32;
-                *var_type = VT_FILETIME as u32;
-            }
-            7 => {
-                *prop_id = PropId::Attrib as u32;
-                *var_type = VT_UI4 as u32;
-            }
-            8 => {
-                *prop_id = PropId::Crc as u32;
-                *var_type = VT_UI4 as u32;
-            }
-            9 => {
-                *prop_id = PropId::Encrypted as u32;
-                *var_type = VT_BOOL as u32;
-            }
-            _ => return E_INVALIDARG,
-        }
+            S_OK
+        },
+    )
+}
 
-        S_OK
+/// The plugin's archive properties as listed to 7-Zip after PhySize.
+///
+/// PhySize is always listed first, and each ID is listed once: a property
+/// the plugin reports twice keeps its first value, as `GetArchiveProperty`
+/// returns that one.
+fn listed_archive_properties<T: ArchiveReader>(inner: &T) -> Vec<ArchiveProperty> {
+    let mut listed: Vec<ArchiveProperty> = Vec::new();
+    for property in inner.archive_properties() {
+        let id = property.id();
+        if id != PropId::PHY_SIZE && listed.iter().all(|p| p.id() != id) {
+            listed.push(property);
+        }
     }
+    listed
 }
 
 unsafe extern "system" fn get_number_of_archive_properties<T: ArchiveReader>(
-    _this: *mut PluginHandler<T>,
+    this: *mut PluginHandler<T>,
     num_props: *mut u32,
 ) -> HRESULT {
-    unsafe {
-        if !num_props.is_null() {
-            *num_props = 1;
-        }
-        S_OK
-    }
+    guard(
+        format_args!("IInArchive::GetNumberOfArchiveProperties({:p})", this),
+        E_FAIL,
+        || unsafe {
+            let lock = lock_handler(this);
+            let HandlerView::Handler(handler) = view_handler(this, &lock) else {
+                return busy("IInArchive::GetNumberOfArchiveProperties");
+            };
+            if !num_props.is_null() {
+                // PhySize, then whatever the plugin reports
+                *num_props = 1 + listed_archive_properties(&handler.inner).len() as u32;
+            }
+            S_OK
+        },
+    )
 }
 
 unsafe extern "system" fn get_archive_property_info<T: ArchiveReader>(
-    _this: *mut PluginHandler<T>,
+    this: *mut PluginHandler<T>,
     index: u32,
     name: *mut c_void,
     prop_id: *mut u32,
     var_type: *mut u32,
 ) -> HRESULT {
-    unsafe {
-        use super::propvariant::VT_UI8;
-
-        if name.is_null() || prop_id.is_null() || var_type.is_null() {
-            return E_INVALIDARG;
-        }
+    guard(
+        format_args!(
+            "IInArchive::GetArchivePropertyInfo({:p}, index={})",
+            this, index
+        ),
+        E_FAIL,
+        || unsafe {
+            let lock = lock_handler(this);
+            use super::propvariant::{VT_UI8, var_type as prop_var_type};
+
+            if name.is_null() || prop_id.is_null() || var_type.is_null() {
+                return E_INVALIDARG;
+            }
 
-        *(name as *mut BSTR) = BSTR::default();
+            *(name as *mut BSTR) = BSTR::default();
 
-        match index {
-            0 => {
-                *prop_id = 4; // PhySize
+            if index == 0 {
+                *prop_id = PropId::PHY_SIZE.0;
                 *var_type = VT_UI8 as u32;
+                return S_OK;
             }
-            _ => return E_INVALIDARG,
-        }
 
-        S_OK
-    }
+            let HandlerView::Handler(handler) = view_handler(this, &lock) else {
+                return busy("IInArchive::GetArchivePropertyInfo");
+            };
+            let Some(property) = listed_archive_properties(&handler.inner)
+                .into_iter()
+                .nth(index as usize - 1)
+            else {
+                return E_INVALIDARG;
+            };
+
+            *prop_id = property.id().0;
+            *var_type = prop_var_type(property.value().prop_type()) as u32;
+
+            S_OK
+        },
+    )
 }
 
 // =============================================================================
 // IOutArchive implementation
 // =============================================================================
 
-const NFILETIME_WINDOWS: u32 = 0;
-
 // IOutArchive wrapper functions that convert out_vtbl pointer to handler base
 unsafe fn out_vtbl_to_handler<T: ArchiveReader>(
     out_vtbl_ptr: *mut PluginHandler<T>,
@@ -969,16 +1820,20 @@ unsafe extern "system" fn out_release<T: ArchiveReader>(this: *mut PluginHandler
 }
 
 unsafe extern "system" fn get_file_time_type<T: ArchiveReader>(
-    _this: *mut PluginHandler<T>,
+    this: *mut PluginHandler<T>,
     time_type: *mut u32,
 ) -> HRESULT {
-    unsafe {
-        if time_type.is_null() {
-            return E_POINTER;
-        }
-        *time_type = NFILETIME_WINDOWS;
-        S_OK
-    }
+    guard(
+        format_args!("IOutArchive::GetFileTimeType({:p})", this),
+        E_FAIL,
+        || unsafe {
+            if time_type.is_null() {
+                return E_POINTER;
+            }
+            *time_type = super::propvariant::file_time_type(T::time_precision());
+            S_OK
+        },
+    )
 }
 
 unsafe extern "system" fn update_items<T: ArchiveReader + ArchiveUpdater>(
@@ -988,33 +1843,97 @@ unsafe extern "system" fn update_items<T: ArchiveReader + ArchiveUpdater>(
     update_callback: *mut c_void,
 ) -> HRESULT {
     unsafe {
-        let handler = &mut *out_vtbl_to_handler(this);
+        let base = out_vtbl_to_handler(this);
 
-        if out_stream.is_null() || update_callback.is_null() {
-            return E_INVALIDARG;
-        }
+        // A panic skips the cleanup below; the handler is reset instead
+        guard_handler(
+            base,
+            format_args!(
+                "IOutArchive::UpdateItems({:p}, num_items={}, callback={:p})",
+                base, num_items, update_callback
+            ),
+            || {
+                if out_stream.is_null() || update_callback.is_null() {
+                    return E_INVALIDARG;
+                }
+
+                // Inner function that does the actual work - allows us to use ? for early returns
+                // while ensuring cleanup always happens in the outer function
+                let result = update_items_inner(base, out_stream, num_items, update_callback);
+
+                // ALWAYS clean up, regardless of success or failure
+                let handler = &mut *base;
+                handler.snapshot = None;
+                handler.inner.close();
+                handler.is_open = false;
 
-        // Inner function that does the actual work - allows us to use ? for early returns
-        // while ensuring cleanup always happens in the outer function
-        let result = update_items_inner(handler, out_stream, num_items, update_callback);
+                handler.release_stream();
 
-        // ALWAYS clean up, regardless of success or failure
-        handler.inner.close();
-        handler.is_open = false;
+                result
+            },
+        )
+    }
+}
 
-        if !handler.in_stream.is_null() {
-            IInStream::<c_void>::from_ptr_mut(handler.in_stream).release();
-            handler.in_stream = std::ptr::null_mut();
+/// Read a single property of update item `index` from the update callback.
+unsafe fn get_update_property(
+    callback: &IArchiveUpdateCallback<c_void>,
+    index: u32,
+    prop_id: PropId,
+) -> Result<RawPropVariant, HRESULT> {
+    unsafe {
+        let mut prop = RawPropVariant::default();
+        let hr = callback.get_property(index, prop_id.0, &mut prop as *mut _ as *mut c_void);
+        if hr.is_err() {
+            return Err(hr);
         }
+        Ok(prop)
+    }
+}
 
-        result
+/// Build an `ArchiveItem` describing update item `index` from 7-Zip's properties.
+unsafe fn read_update_item(
+    callback: &IArchiveUpdateCallback<c_void>,
+    index: u32,
+) -> Result<ArchiveItem, HRESULT> {
+    unsafe {
+        let mut path = get_update_property(callback, index, PropId::PATH)?;
+        let name = path.get_bstr().unwrap_or_default();
+        // We own the BSTR allocated by 7-Zip
+        path.clear();
+
+        let is_dir = get_update_property(callback, index, PropId::IS_DIR)?
+            .get_bool()
+            .unwrap_or(false);
+        let size = get_update_property(callback, index, PropId::SIZE)?
+            .get_u64()
+            .unwrap_or(0);
+        let attributes = get_update_property(callback, index, PropId::ATTRIB)?.get_u32();
+        let modified = get_update_property(callback, index, PropId::MTIME)?.get_time();
+        let created = get_update_property(callback, index, PropId::CTIME)?.get_time();
+        let accessed = get_update_property(callback, index, PropId::ATIME)?.get_time();
+
+        Ok(ArchiveItem {
+            name,
+            size: if is_dir { 0 } else { size },
+            modified,
+            created,
+            accessed,
+            is_dir,
+            attributes,
+            ..Default::default()
+        })
     }
 }
 
 /// Inner implementation of update_items that can return early.
 /// Cleanup is handled by the caller.
+///
+/// 7-Zip reads the properties of kept items from the archive while it
+/// answers the update callback, so `this` is only borrowed for each use of
+/// the plugin, and nested reads see a snapshot of the kept items.
 unsafe fn update_items_inner<T: ArchiveReader + ArchiveUpdater>(
-    handler: &mut PluginHandler<T>,
+    this: *mut PluginHandler<T>,
     out_stream: *mut c_void,
     num_items: u32,
     update_callback: *mut c_void,
@@ -1023,11 +1942,14 @@ unsafe fn update_items_inner<T: ArchiveReader + ArchiveUpdater>(
         // Get callback wrapper for type-safe method calls
         let callback = IArchiveUpdateCallback::<c_void>::from_ptr_mut(update_callback);
 
-        use crate::types::UpdateItem;
+        use crate::types::{ItemData, UpdateItem, UpdatePlan};
+        // Results of the new items the plugin read, reported once it succeeded
+        let results = RefCell::new(Vec::new());
         let mut updates = Vec::new();
         let mut total_size: u64 = 0;
 
-        // First pass: gather update info and calculate total size
+        // What 7-Zip does with each item: (new data, new properties, index in archive)
+        let mut infos = Vec::with_capacity(num_items as usize);
         for i in 0..num_items {
             let mut new_data: i32 = 0;
             let mut new_props: i32 = 0;
@@ -1042,22 +1964,35 @@ unsafe fn update_items_inner<T: ArchiveReader + ArchiveUpdater>(
             if hr.is_err() {
                 return hr;
             }
+            infos.push((new_data != 0, new_props != 0, index_in_archive));
+        }
 
-            if new_data != 0 {
+        // Snapshot the kept items before 7-Zip gets a chance to read them
+        let mut kept_indices: Vec<usize> = infos
+            .iter()
+            .filter(|&&(new_data, _, index)| !new_data && index != u32::MAX)
+            .map(|&(_, _, index)| index as usize)
+            .collect();
+        kept_indices.sort_unstable();
+        kept_indices.dedup();
+        let snapshot = ItemSnapshot::capture(&(*this).inner, kept_indices);
+        (*this).snapshot = Some(snapshot);
+
+        // First pass: calculate total size
+        for (i, &(new_data, _, index_in_archive)) in (0..num_items).zip(&infos) {
+            if new_data {
                 // New file - get size property for progress tracking
                 let mut size_prop = RawPropVariant::default();
                 let _ = callback.get_property(
                     i,
-                    PropId::Size as u32,
+                    PropId::SIZE.0,
                     &mut size_prop as *mut _ as *mut c_void,
                 );
                 let file_size = size_prop.get_u64().unwrap_or(0);
                 total_size += file_size;
             } else if index_in_archive != u32::MAX {
                 // Copying existing item - get its size
-                if let Some(item) = handler.inner.get_item(index_in_archive as usize) {
-                    total_size += item.size;
-                }
+                total_size += item_size(&(*this).inner, index_in_archive as usize);
             }
         }
 
@@ -1065,90 +2000,80 @@ unsafe fn update_items_inner<T: ArchiveReader + ArchiveUpdater>(
         let _ = callback.set_total(total_size);
 
         // Second pass: collect data
-        for i in 0..num_items {
-            let mut new_data: i32 = 0;
-            let mut new_props: i32 = 0;
-            let mut index_in_archive: u32 = u32::MAX;
-
-            let hr = callback.get_update_item_info(
-                i,
-                &mut new_data,
-                &mut new_props,
-                &mut index_in_archive,
-            );
-            if hr.is_err() {
-                return hr;
-            }
-
-            if new_data != 0 {
-                // Check if this is a directory - skip directories as most archive
-                // formats don't need explicit directory entries (paths contain folders)
-                let mut is_dir_prop = RawPropVariant::default();
-                let _ = callback.get_property(
-                    i,
-                    PropId::IsDir as u32,
-                    &mut is_dir_prop as *mut _ as *mut c_void,
-                );
-                let is_dir = is_dir_prop.get_bool().unwrap_or(false);
+        for (i, &(new_data, new_props, index_in_archive)) in (0..num_items).zip(&infos) {
+            if new_data {
+                // New item - read its metadata (path, size, times, attributes)
+                let item = match read_update_item(callback, i) {
+                    Ok(item) => item,
+                    Err(hr) => return hr,
+                };
 
-                if is_dir {
-                    // Skip directories - report success and continue
+                let data = if item.is_dir {
+                    // Directories have no data stream - report success now
                     let _ = callback.set_operation_result(NRESULT_OK);
-                    continue;
-                }
-
-                // New file - get name and data
-                let mut prop = RawPropVariant::default();
-                let hr = callback.get_property(
-                    i,
-                    PropId::Path as u32,
-                    &mut prop as *mut _ as *mut c_void,
-                );
-                if hr.is_err() {
-                    return hr;
-                }
-
-                let name = prop.get_bstr().unwrap_or_default();
-
-                // Get input stream
-                let mut in_stream: *mut c_void = std::ptr::null_mut();
-                let hr = callback.get_stream(i, &mut in_stream);
-                if hr.is_err() {
-                    return hr;
-                }
-
-                let data = if !in_stream.is_null() {
-                    let result = read_sequential_stream(in_stream);
-                    // Release stream
-                    ISequentialInStream::<c_void>::from_ptr_mut(in_stream).release();
-                    result.unwrap_or_default()
+                    ItemData::empty()
                 } else {
-                    Vec::new()
+                    // The input stream is opened lazily when the plugin reads the item,
+                    // and its operation result is reported after the update succeeded.
+                    // Don't report progress here - the plugin will report progress
+                    // during update_streaming when the data is actually written.
+                    ItemData::new(UpdateItemStream::new(update_callback, i, &results))
                 };
 
-                // Don't report progress here - the plugin will report progress
-                // during update_streaming when the data is actually written.
-
-                updates.push(UpdateItem::AddNew { name, data });
-
-                // Report operation result for this item
-                let _ = callback.set_operation_result(NRESULT_OK);
+                updates.push(UpdateItem::AddNew { item, data });
             } else if index_in_archive != u32::MAX {
                 // Copy existing item - don't report progress here since no actual work
                 // is done during collection. Progress will be reported by the plugin
                 // during update_streaming when the data is actually processed.
+                let index = index_in_archive as usize;
+
+                // Renames and timestamp/attribute changes arrive as new properties
+                // on an item whose data is unchanged
+                let (new_name, new_props) = if new_props {
+                    let props = match read_update_item(callback, i) {
+                        Ok(props) => props,
+                        Err(hr) => return hr,
+                    };
+                    let renamed = (*this)
+                        .inner
+                        .item_property(index, PropId::PATH)
+                        .is_none_or(|existing| existing.as_str() != Some(props.name.as_str()));
+                    (renamed.then(|| props.name.clone()), Some(props))
+                } else {
+                    (None, None)
+                };
+
                 updates.push(UpdateItem::CopyExisting {
-                    index: index_in_archive as usize,
-                    new_name: None,
+                    index,
+                    new_name,
+                    new_props,
                 });
 
                 // Report operation result for this item
                 let _ = callback.set_operation_result(NRESULT_OK);
             }
-            // else: item is being deleted (new_data == 0 && index_in_archive == MAX)
-            // - don't add to updates list, which removes it from the archive
         }
 
+        // Existing items that 7-Zip did not list are being deleted
+        let mut kept = vec![false; (*this).inner.item_count()];
+        for update in &updates {
+            if let UpdateItem::CopyExisting { index, .. } = update
+                && let Some(flag) = kept.get_mut(*index)
+            {
+                *flag = true;
+            }
+        }
+        let deleted = kept
+            .iter()
+            .enumerate()
+            .filter_map(|(index, &kept)| (!kept).then_some(index))
+            .collect();
+
+        let plan = UpdatePlan {
+            items: updates,
+            deleted,
+        };
+
         // Create streaming writer for output
         let mut writer = SeqOutStreamWriter::new(out_stream);
 
@@ -1159,6 +2084,9 @@ unsafe fn update_items_inner<T: ArchiveReader + ArchiveUpdater>(
         // Create progress callback that reports to 7-Zip
         // The inner format reports (completed, total) in whatever units make sense for it.
         // We scale this to match total_size (what we told 7-Zip) using the ratio.
+        // If 7-Zip rejects the progress update (E_ABORT when the user presses Cancel),
+        // the callback returns false so the plugin can stop, and we remember it.
+        let cancelled = Cell::new(false);
         let mut progress_fn = |write_completed: u64, write_total: u64| -> bool {
             let scaled = if write_total > 0 {
                 // Scale: (completed / total) * total_size
@@ -1167,18 +2095,20 @@ unsafe fn update_items_inner<T: ArchiveReader + ArchiveUpdater>(
             } else {
                 0
             };
-            let _ = callback.set_completed(&scaled);
-            true // continue operation
+            if callback.set_completed(&scaled).is_err() {
+                cancelled.set(true);
+            }
+            !cancelled.get()
         };
 
         // Create reader for existing archive (if we have one)
-        if handler.in_stream.is_null() {
+        if (*this).in_stream.is_null() {
             // No existing archive - create empty reader
             let mut empty_reader = std::io::Cursor::new(&[] as &[u8]);
-            let result = handler.inner.update_streaming_with_password(
+            let result = (*this).inner.update_streaming_with_password(
                 &mut empty_reader,
                 0,
-                updates,
+                plan,
                 &mut writer,
                 Some(&mut progress_fn),
                 password_provider
@@ -1186,27 +2116,28 @@ unsafe fn update_items_inner<T: ArchiveReader + ArchiveUpdater>(
                     .map(|p| p as &dyn PasswordProvider),
             );
             match result {
+                _ if cancelled.get() => E_ABORT,
                 Ok(_) => {
                     // Report 100% completion after write phase finishes
                     // Re-get the callback wrapper since progress_fn borrowed it
                     let cb = IArchiveUpdateCallback::<c_void>::from_ptr_mut(update_callback);
                     let _ = cb.set_completed(&total_size);
-                    S_OK
+                    report_update_results(cb, &results)
                 }
-                Err(_) => S_FALSE,
+                Err(e) => error_to_hresult(&e, E_FAIL),
             }
         } else {
             // Use existing archive stream
-            let mut reader = match InStreamReader::new(handler.in_stream) {
+            let mut reader = match InStreamReader::new((*this).in_stream) {
                 Ok(r) => r,
-                Err(_) => return S_FALSE,
+                Err(_) => return E_FAIL,
             };
             let size = reader.size();
 
-            let result = handler.inner.update_streaming_with_password(
+            let result = (*this).inner.update_streaming_with_password(
                 &mut reader,
                 size,
-                updates,
+                plan,
                 &mut writer,
                 Some(&mut progress_fn),
                 password_provider
@@ -1214,14 +2145,15 @@ unsafe fn update_items_inner<T: ArchiveReader + ArchiveUpdater>(
                     .map(|p| p as &dyn PasswordProvider),
             );
             match result {
+                _ if cancelled.get() => E_ABORT,
                 Ok(_) => {
                     // Report 100% completion after write phase finishes
                     // Re-get the callback wrapper since progress_fn borrowed it
                     let cb = IArchiveUpdateCallback::<c_void>::from_ptr_mut(update_callback);
                     let _ = cb.set_completed(&total_size);
-                    S_OK
+                    report_update_results(cb, &results)
                 }
-                Err(_) => S_FALSE,
+                Err(e) => error_to_hresult(&e, E_FAIL),
             }
         }
     }
@@ -1324,11 +2256,72 @@ impl<T: ArchiveReader> RegisteredFormat<T> {
             in_vtbl: self.in_vtbl,
             out_vtbl: self.out_vtbl,
             ref_count: AtomicU32::new(1),
+            lock: Arc::new(HandlerLock::new()),
             inner: T::default(),
             in_stream: std::ptr::null_mut(),
             archive_size: 0,
             is_open: false,
+            error_flags: 0,
+            snapshot: None,
         });
         Box::into_raw(handler) as *mut c_void
     }
 }
+
+/// Type-erased view of a [`RegisteredFormat`].
+///
+/// The registration macro builds a static table of these so a single DLL
+/// can dispatch `CreateObject` and `GetHandlerProperty2` across several formats.
+pub trait FormatRegistration: Sync {
+    /// Class ID of the format.
+    fn class_id(&self) -> GUID;
+
+    /// Create a handler and return the requested interface.
+    ///
+    /// # Safety
+    /// `out_object` must be a valid pointer.
+    unsafe fn create_object(&self, iid: &GUID, out_object: *mut *mut c_void) -> HRESULT;
+
+    /// Write a handler property (name, extension, signature, ...) for this format.
+    ///
+    /// # Safety
+    /// `value` must point to a valid PROPVARIANT.
+    unsafe fn handler_property(&self, prop_id: u32, value: &mut RawPropVariant);
+
+    /// IsArc function used by 7-Zip to check file headers for this format.
+    fn is_arc_func(&self) -> super::exports::IsArcFunc;
+}
+
+impl<T: ArchiveReader> FormatRegistration for RegisteredFormat<T> {
+    fn class_id(&self) -> GUID {
+        super::exports::guid_from_bytes(&T::CLASS_ID)
+    }
+
+    unsafe fn create_object(&self, iid: &GUID, out_object: *mut *mut c_void) -> HRESULT {
+        unsafe {
+            if *iid == IID_IINARCHIVE {
+                *out_object = self.create_handler();
+                return S_OK;
+            }
+
+            if *iid == IID_IOUTARCHIVE && T::supports_write() {
+                // Return pointer to out_vtbl field
+                let handler = self.create_handler() as *mut PluginHandler<T>;
+                *out_object = &raw mut (*handler).out_vtbl as *mut c_void;
+                return S_OK;
+            }
+
+            // Unknown interface
+            *out_object = std::ptr::null_mut();
+            E_NOINTERFACE
+        }
+    }
+
+    unsafe fn handler_property(&self, prop_id: u32, value: &mut RawPropVariant) {
+        unsafe { super::exports::set_handler_property::<T>(prop_id, value) }
+    }
+
+    fn is_arc_func(&self) -> super::exports::IsArcFunc {
+        super::exports::is_arc::<T>
+    }
+}
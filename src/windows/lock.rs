@@ -0,0 +1,190 @@
+//! Serialization of calls into a handler from several threads.
+//!
+//! 7-Zip File Manager may query a handler from its UI thread while another
+//! thread extracts from it, but plugin code is written against `&mut self`.
+//! Every entry point that touches the plugin holds a [`HandlerLock`] for the
+//! duration of the call, so calls from different threads run one at a time.
+//!
+//! The lock is reentrant: 7-Zip calls back into the handler on the thread
+//! that already holds it (e.g. `GetProperty` from inside the extract
+//! callback's `GetStream`), and those calls must not deadlock.
+//!
+//! A call that changes the handler enters it with
+//! [`HandlerLock::lock_exclusive`] and lends the plugin out as `&mut` while
+//! it calls back into 7-Zip. Nothing nested in such a call may touch the
+//! plugin: a second exclusive entry is refused, and nested reads are told so
+//! by [`HandlerLockGuard::nested_in_exclusive`] and must answer from data kept
+//! outside the plugin.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+
+/// Reentrant lock owned by one thread at a time.
+pub(crate) struct HandlerLock {
+    owner: Mutex<Owner>,
+    released: Condvar,
+}
+
+/// The thread inside the handler and how many calls deep it is.
+#[derive(Default)]
+struct Owner {
+    thread: Option<ThreadId>,
+    depth: usize,
+    /// Whether the outermost call entered exclusively
+    exclusive: bool,
+}
+
+impl HandlerLock {
+    pub(crate) fn new() -> Self {
+        Self {
+            owner: Mutex::new(Owner::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until no other thread is inside the handler, then enter it.
+    ///
+    /// For calls that only read the handler; they may be nested in another
+    /// call on the same thread.
+    pub(crate) fn lock(self: &Arc<Self>) -> HandlerLockGuard {
+        self.enter(false).expect("shared entry is always granted")
+    }
+
+    /// Like [`lock`](Self::lock), for calls that change the handler.
+    ///
+    /// Returns `None` if this thread is already inside the handler, since the
+    /// outer call may still hold a reference to it.
+    pub(crate) fn lock_exclusive(self: &Arc<Self>) -> Option<HandlerLockGuard> {
+        self.enter(true)
+    }
+
+    fn enter(self: &Arc<Self>, exclusive: bool) -> Option<HandlerLockGuard> {
+        let current = thread::current().id();
+        let mut owner = self.owner();
+        while owner.thread.is_some_and(|thread| thread != current) {
+            owner = self
+                .released
+                .wait(owner)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if exclusive && owner.depth > 0 {
+            return None;
+        }
+        let nested_in_exclusive = owner.exclusive;
+        owner.thread = Some(current);
+        owner.depth += 1;
+        owner.exclusive |= exclusive;
+
+        Some(HandlerLockGuard {
+            lock: Arc::clone(self),
+            nested_in_exclusive,
+        })
+    }
+
+    // The mutex is only held for a few instructions, never while plugin code
+    // runs, so a poisoned state is still consistent
+    fn owner(&self) -> MutexGuard<'_, Owner> {
+        self.owner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Leaves the handler when dropped, including while unwinding from a panic.
+pub(crate) struct HandlerLockGuard {
+    lock: Arc<HandlerLock>,
+    nested_in_exclusive: bool,
+}
+
+impl HandlerLockGuard {
+    /// Whether this call runs inside a call that entered exclusively, which
+    /// still holds the plugin mutably.
+    pub(crate) fn nested_in_exclusive(&self) -> bool {
+        self.nested_in_exclusive
+    }
+}
+
+impl Drop for HandlerLockGuard {
+    fn drop(&mut self) {
+        let mut owner = self.lock.owner();
+        owner.depth -= 1;
+        if owner.depth == 0 {
+            owner.thread = None;
+            owner.exclusive = false;
+            drop(owner);
+            self.lock.released.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn same_thread_can_reenter() {
+        let lock = Arc::new(HandlerLock::new());
+        let outer = lock.lock();
+        let inner = lock.lock();
+        assert_eq!(lock.owner().depth, 2);
+        assert!(!inner.nested_in_exclusive());
+
+        drop(inner);
+        drop(outer);
+        assert_eq!(lock.owner().depth, 0);
+        assert!(lock.owner().thread.is_none());
+    }
+
+    #[test]
+    fn exclusive_entry_is_not_nested() {
+        let lock = Arc::new(HandlerLock::new());
+        let outer = lock.lock_exclusive().expect("first entry");
+        assert!(lock.lock_exclusive().is_none());
+
+        // Reading from inside the call is still allowed, but it is told so
+        let nested = lock.lock();
+        assert!(nested.nested_in_exclusive());
+        drop(nested);
+        drop(outer);
+
+        let outer = lock.lock_exclusive().expect("entry after release");
+        assert!(!outer.nested_in_exclusive());
+        drop(outer);
+        assert!(!lock.lock().nested_in_exclusive());
+    }
+
+    #[test]
+    fn other_threads_wait_for_release() {
+        let lock = Arc::new(HandlerLock::new());
+        let guard = lock.lock();
+        let nested = lock.lock();
+
+        let (entered, entry) = mpsc::channel();
+        let waiter = thread::spawn({
+            let lock = Arc::clone(&lock);
+            move || {
+                let _guard = lock.lock_exclusive().expect("not nested");
+                entered.send(()).unwrap();
+            }
+        });
+
+        assert!(entry.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(nested);
+        assert!(entry.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(guard);
+        entry.recv().unwrap();
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn released_when_unwinding() {
+        let lock = Arc::new(HandlerLock::new());
+        let result = std::panic::catch_unwind(|| {
+            let _guard = lock.lock();
+            panic!("plugin panicked");
+        });
+        assert!(result.is_err());
+        assert_eq!(lock.owner().depth, 0);
+        assert!(lock.owner().thread.is_none());
+    }
+}
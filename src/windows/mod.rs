@@ -1,6 +1,42 @@
-//! Windows-specific 7-Zip plugin implementation using COM interfaces.
+//! 7-Zip plugin implementation using COM interfaces.
+//!
+//! Only Windows hosts load plugins. On other platforms the bridge is built
+//! with the `testing` feature, so the test host can drive it.
 
 pub mod com;
 pub mod exports;
 pub mod handler;
+mod lock;
+mod panic;
 pub mod propvariant;
+pub mod sys;
+
+/// Path of the DLL this crate is linked into.
+#[cfg(all(windows, feature = "debug"))]
+pub(crate) fn module_path() -> Option<std::path::PathBuf> {
+    use windows::Win32::Foundation::HMODULE;
+    use windows::Win32::System::LibraryLoader::{
+        GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+        GetModuleFileNameW, GetModuleHandleExW,
+    };
+    use windows::core::PCWSTR;
+
+    // Any address inside the DLL identifies it
+    let address = module_path as *const u16;
+    let mut module = HMODULE::default();
+    unsafe {
+        GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            PCWSTR(address),
+            &mut module,
+        )
+        .ok()?;
+    }
+
+    let mut buffer = vec![0u16; 1024];
+    let len = unsafe { GetModuleFileNameW(Some(module), &mut buffer) } as usize;
+    if len == 0 || len >= buffer.len() {
+        return None;
+    }
+    Some(String::from_utf16_lossy(&buffer[..len]).into())
+}
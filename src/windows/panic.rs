@@ -0,0 +1,57 @@
+//! Panic containment at the COM boundary.
+//!
+//! A panic unwinding out of an `extern "system"` function aborts the host
+//! process (7-Zip or its File Manager), so every entry point that runs plugin
+//! code catches it here and returns an error to 7-Zip instead.
+//!
+//! This only works with the default `panic = "unwind"` strategy.
+
+use std::any::Any;
+use std::fmt;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+/// Run `f`, returning `on_panic` instead if it panics.
+///
+/// `call` describes the COM method and its arguments; it is traced together
+/// with the result.
+pub(crate) fn guard<R: fmt::Debug>(
+    call: fmt::Arguments<'_>,
+    on_panic: R,
+    f: impl FnOnce() -> R,
+) -> R {
+    guard_or_else(call, f, || on_panic)
+}
+
+/// Like [`guard`], computing the result with `on_panic` after a panic.
+pub(crate) fn guard_or_else<R: fmt::Debug>(
+    call: fmt::Arguments<'_>,
+    f: impl FnOnce() -> R,
+    on_panic: impl FnOnce() -> R,
+) -> R {
+    crate::log_trace!("{}", call);
+    let result = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(payload) => {
+            log_panic(call, payload.as_ref());
+            on_panic()
+        }
+    };
+    crate::log_trace!("{} -> {:?}", call, result);
+    result
+}
+
+/// The message a panic was raised with.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Record a panic caught in COM method `call`.
+pub(crate) fn log_panic(call: impl fmt::Display, payload: &(dyn Any + Send)) {
+    crate::log_error!("{} panicked: {}", call, panic_message(payload));
+}
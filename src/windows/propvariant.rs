@@ -3,38 +3,188 @@
 //! 7-Zip uses 16-byte PROPVARIANT, not the 24-byte version from the windows crate.
 
 use std::ffi::c_void;
-use windows::Win32::Foundation::SysFreeString;
-use windows::core::BSTR;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use std::time::SystemTime;
+use super::sys::{BSTR, SysAllocStringByteLen};
+use crate::calendar::{civil_from_days, days_from_civil, days_in_month};
+use crate::types::{PropType, PropValue, TimePrecision};
 
 /// VT (variant type) constants.
 pub const VT_EMPTY: u16 = 0;
 pub const VT_UI4: u16 = 19;
 pub const VT_UI8: u16 = 21;
+pub const VT_I8: u16 = 20;
 pub const VT_BSTR: u16 = 8;
 pub const VT_BOOL: u16 = 11;
 pub const VT_FILETIME: u16 = 64;
 
+/// VT type 7-Zip expects for values of a property type.
+pub fn var_type(prop_type: PropType) -> u16 {
+    match prop_type {
+        PropType::String | PropType::Bytes => VT_BSTR,
+        PropType::U32 => VT_UI4,
+        PropType::U64 => VT_UI8,
+        PropType::I64 => VT_I8,
+        PropType::Bool => VT_BOOL,
+        PropType::FileTime => VT_FILETIME,
+    }
+}
+
 /// Windows FILETIME epoch: January 1, 1601
 /// Difference between Unix epoch (1970) and Windows epoch (1601) in 100ns intervals
 const FILETIME_UNIX_DIFF: u64 = 116444736000000000;
 
+/// 100ns intervals per second
+const FILETIME_TICKS_PER_SECOND: i128 = 10_000_000;
+
+// Time precision codes stored in PROPVARIANT wReserved1 (k_PropVar_TimePrec_*)
+pub const TIME_PREC_UNIX: u16 = 1;
+pub const TIME_PREC_DOS: u16 = 2;
+pub const TIME_PREC_100NS: u16 = 16 + 7;
+pub const TIME_PREC_1NS: u16 = 16 + 9;
+
+/// Precision code 7-Zip uses for a format's time precision.
+pub fn time_precision_code(precision: TimePrecision) -> u16 {
+    match precision {
+        TimePrecision::Windows => TIME_PREC_100NS,
+        TimePrecision::Unix => TIME_PREC_UNIX,
+        TimePrecision::Dos => TIME_PREC_DOS,
+        TimePrecision::Nanoseconds => TIME_PREC_1NS,
+    }
+}
+
+/// NFileTimeType value returned from IOutArchive::GetFileTimeType.
+pub fn file_time_type(precision: TimePrecision) -> u32 {
+    match precision {
+        TimePrecision::Windows => 0,
+        TimePrecision::Unix => 1,
+        TimePrecision::Dos => 2,
+        TimePrecision::Nanoseconds => 3,
+    }
+}
+
 /// Convert a `SystemTime` to Windows FILETIME format (100ns intervals since 1601-01-01).
+///
+/// Sub-100ns precision is truncated; times before 1601 clamp to 0.
 pub fn systemtime_to_filetime(time: SystemTime) -> u64 {
-    match time.duration_since(std::time::UNIX_EPOCH) {
-        Ok(duration) => {
-            // Convert to 100ns intervals and add the epoch difference
-            let nanos_100 = duration.as_nanos() / 100;
-            FILETIME_UNIX_DIFF + nanos_100 as u64
-        }
-        Err(_) => {
-            // Time is before Unix epoch - this is rare but possible
-            0
+    let (secs, nanos) = systemtime_to_unix(time);
+    unix_to_filetime(secs, nanos)
+}
+
+/// Convert a Windows FILETIME (100ns intervals since 1601-01-01) to a `SystemTime`.
+///
+/// Returns `None` if the platform's `SystemTime` can't represent it.
+pub fn filetime_to_systemtime(filetime: u64) -> Option<SystemTime> {
+    let (secs, nanos) = filetime_to_unix(filetime);
+    unix_to_systemtime(secs, nanos)
+}
+
+/// Convert a `SystemTime` to Unix seconds and nanoseconds.
+///
+/// Times before 1970 give negative seconds; `nanos` is always the positive
+/// offset within the second, so -0.5s is `(-1, 500_000_000)`.
+pub fn systemtime_to_unix(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+        Err(err) => {
+            let before = err.duration();
+            let secs = -(before.as_secs() as i64);
+            match before.subsec_nanos() {
+                0 => (secs, 0),
+                nanos => (secs - 1, 1_000_000_000 - nanos),
+            }
         }
     }
 }
 
+/// Convert Unix seconds and nanoseconds (see [`systemtime_to_unix`]) to a `SystemTime`.
+///
+/// Returns `None` if the platform's `SystemTime` can't represent it (e.g.
+/// dates before 1601 on Windows).
+pub fn unix_to_systemtime(secs: i64, nanos: u32) -> Option<SystemTime> {
+    let nanos = Duration::from_nanos(nanos.into());
+    let whole = if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+    };
+    whole?.checked_add(nanos)
+}
+
+/// Convert Unix seconds and nanoseconds to a FILETIME, clamping to its range.
+pub fn unix_to_filetime(secs: i64, nanos: u32) -> u64 {
+    let ticks = secs as i128 * FILETIME_TICKS_PER_SECOND
+        + (nanos / 100) as i128
+        + FILETIME_UNIX_DIFF as i128;
+    ticks.clamp(0, u64::MAX as i128) as u64
+}
+
+/// Convert a FILETIME to Unix seconds and nanoseconds.
+pub fn filetime_to_unix(filetime: u64) -> (i64, u32) {
+    let ticks = filetime as i128 - FILETIME_UNIX_DIFF as i128;
+    let secs = ticks.div_euclid(FILETIME_TICKS_PER_SECOND) as i64;
+    let nanos = (ticks.rem_euclid(FILETIME_TICKS_PER_SECOND) * 100) as u32;
+    (secs, nanos)
+}
+
+/// DOS time of 1980-01-01 00:00:00, the earliest representable.
+const DOS_TIME_MIN: u32 = 0x0021_0000;
+/// DOS time of 2107-12-31 23:59:58, the latest representable.
+const DOS_TIME_MAX: u32 = 0xFF9F_BF7D;
+
+/// Convert an MS-DOS date/time (date in the high 16 bits) to a `SystemTime`.
+///
+/// DOS times carry no time zone; this treats them as UTC. Returns `None`
+/// if a field is out of range, including days past the end of the month
+/// (e.g. February 29 outside leap years).
+pub fn dos_time_to_systemtime(dos_time: u32) -> Option<SystemTime> {
+    let date = dos_time >> 16;
+    let year = 1980 + (date >> 9) as i64;
+    let month = (date >> 5) & 0x0F;
+    let day = date & 0x1F;
+    let hour = ((dos_time >> 11) & 0x1F) as i64;
+    let minute = ((dos_time >> 5) & 0x3F) as i64;
+    let second = ((dos_time & 0x1F) * 2) as i64;
+
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    unix_to_systemtime(days * 86_400 + hour * 3_600 + minute * 60 + second, 0)
+}
+
+/// Convert a `SystemTime` to an MS-DOS date/time (as UTC).
+///
+/// DOS times have 2-second steps; like 7-Zip, this rounds up so a file
+/// never looks older than it is. Times outside 1980-2107 are clamped.
+pub fn systemtime_to_dos_time(time: SystemTime) -> u32 {
+    let (secs, nanos) = systemtime_to_unix(time);
+    let mut secs = secs + i64::from(nanos > 0);
+    secs += secs.rem_euclid(2);
+
+    let days = secs.div_euclid(86_400);
+    let in_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    if year < 1980 {
+        return DOS_TIME_MIN;
+    }
+    if year > 2107 {
+        return DOS_TIME_MAX;
+    }
+
+    let date = ((year - 1980) as u32) << 9 | month << 5 | day;
+    let time = ((in_day / 3_600) as u32) << 11
+        | ((in_day / 60 % 60) as u32) << 5
+        | (in_day % 60 / 2) as u32;
+    date << 16 | time
+}
+
 /// Raw 16-byte PROPVARIANT matching 7-Zip's expectations.
 ///
 /// The windows crate's PROPVARIANT is 24 bytes which causes crashes with 7-Zip.
@@ -76,11 +226,13 @@ impl RawPropVariant {
             if self.vt == VT_BSTR {
                 let ptr = self.data as *mut u16;
                 if !ptr.is_null() {
-                    // Reconstruct the BSTR and free it properly
-                    SysFreeString(&BSTR::from_raw(ptr));
+                    // Reconstruct the BSTR; dropping it frees the string
+                    drop(BSTR::from_raw(ptr));
                 }
             }
             self.vt = VT_EMPTY;
+            self.reserved1 = 0;
+            self.reserved2 = 0;
             self.data = 0;
         }
     }
@@ -136,6 +288,19 @@ impl RawPropVariant {
         }
     }
 
+    /// Set an i64 value.
+    ///
+    /// # Safety
+    /// If this PROPVARIANT already contains allocated data (like BSTR),
+    /// the caller must call `clear()` first to avoid memory leaks.
+    pub unsafe fn set_i64(&mut self, value: i64) {
+        unsafe {
+            self.clear();
+            self.vt = VT_I8;
+            self.data = value as u64;
+        }
+    }
+
     /// Set a bool value.
     ///
     /// # Safety
@@ -163,6 +328,24 @@ impl RawPropVariant {
         }
     }
 
+    /// Set a time value, tagged with the precision the format stores it at.
+    ///
+    /// 7-Zip reads the precision from wReserved1 and, at nanosecond
+    /// precision, the nanoseconds below 100ns from wReserved2.
+    ///
+    /// # Safety
+    /// If this PROPVARIANT already contains allocated data (like BSTR),
+    /// the caller must call `clear()` first to avoid memory leaks.
+    pub unsafe fn set_time(&mut self, time: SystemTime, precision: TimePrecision) {
+        unsafe {
+            self.set_filetime(systemtime_to_filetime(time));
+            self.reserved1 = time_precision_code(precision);
+            if precision == TimePrecision::Nanoseconds {
+                self.reserved2 = (systemtime_to_unix(time).1 % 100) as u16;
+            }
+        }
+    }
+
     /// Set a GUID value (as binary blob for ClassId property).
     ///
     /// 7-Zip expects VT_BSTR with raw GUID bytes for ClassId.
@@ -176,8 +359,6 @@ impl RawPropVariant {
             self.clear();
             // 7-Zip reads ClassId as VT_BSTR containing raw GUID bytes
             // Allocate a BSTR that contains the 16 bytes
-            use windows::Win32::Foundation::SysAllocStringByteLen;
-
             let bstr = SysAllocStringByteLen(Some(bytes.as_slice()));
             self.vt = VT_BSTR;
             self.data = bstr.into_raw() as u64;
@@ -195,14 +376,30 @@ impl RawPropVariant {
     pub unsafe fn set_bytes(&mut self, bytes: &[u8]) {
         unsafe {
             self.clear();
-            use windows::Win32::Foundation::SysAllocStringByteLen;
-
             let bstr = SysAllocStringByteLen(Some(bytes));
             self.vt = VT_BSTR;
             self.data = bstr.into_raw() as u64;
         }
     }
 
+    /// Set a typed property value.
+    ///
+    /// # Safety
+    /// Same as the individual setters.
+    pub unsafe fn set_value(&mut self, value: &PropValue) {
+        unsafe {
+            match value {
+                PropValue::String(s) => self.set_bstr(s),
+                PropValue::U32(v) => self.set_u32(*v),
+                PropValue::U64(v) => self.set_u64(*v),
+                PropValue::I64(v) => self.set_i64(*v),
+                PropValue::Bool(v) => self.set_bool(*v),
+                PropValue::FileTime(t) => self.set_filetime(systemtime_to_filetime(*t)),
+                PropValue::Bytes(b) => self.set_bytes(b),
+            }
+        }
+    }
+
     /// Extract a BSTR string from this PROPVARIANT.
     ///
     /// # Safety
@@ -249,6 +446,49 @@ impl RawPropVariant {
         }
     }
 
+    /// Extract a FILETIME value from this PROPVARIANT.
+    ///
+    /// Returns `Some(value)` if the type is VT_FILETIME, `None` otherwise.
+    pub fn get_filetime(&self) -> Option<u64> {
+        if self.vt == VT_FILETIME {
+            Some(self.data)
+        } else {
+            None
+        }
+    }
+
+    /// Extract a time value from this PROPVARIANT, including the
+    /// sub-100ns part 7-Zip passes at nanosecond precision.
+    ///
+    /// Returns `Some(value)` if the type is VT_FILETIME, `None` otherwise.
+    pub fn get_time(&self) -> Option<SystemTime> {
+        let filetime = self.get_filetime()?;
+        let time = filetime_to_systemtime(filetime)?;
+        if self.reserved1 == TIME_PREC_1NS && self.reserved2 < 100 {
+            return time.checked_add(Duration::from_nanos(self.reserved2.into()));
+        }
+        Some(time)
+    }
+
+    /// Read this PROPVARIANT as a typed value.
+    ///
+    /// Strings come back as `PropValue::String`; `None` for VT_EMPTY and
+    /// unsupported types.
+    ///
+    /// # Safety
+    /// A VT_BSTR value must point to a valid BSTR.
+    pub unsafe fn get_value(&self) -> Option<PropValue> {
+        match self.vt {
+            VT_BSTR => unsafe { self.get_bstr() }.map(PropValue::String),
+            VT_UI4 => self.get_u32().map(PropValue::U32),
+            VT_UI8 => self.get_u64().map(PropValue::U64),
+            VT_I8 => Some(PropValue::I64(self.data as i64)),
+            VT_BOOL => self.get_bool().map(PropValue::Bool),
+            VT_FILETIME => self.get_time().map(PropValue::FileTime),
+            _ => None,
+        }
+    }
+
     /// Extract a bool value from this PROPVARIANT.
     ///
     /// Returns `Some(value)` if the type is VT_BOOL, `None` otherwise.
@@ -275,3 +515,137 @@ pub unsafe fn write_propvariant(dest: *mut c_void, prop: &RawPropVariant) {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i64, month: u32, day: u32, secs_of_day: i64) -> SystemTime {
+        unix_to_systemtime(days_from_civil(year, month, day) * 86_400 + secs_of_day, 0).unwrap()
+    }
+
+    #[test]
+    fn unix_time_before_the_epoch() {
+        let half_before = UNIX_EPOCH - Duration::from_millis(500);
+        assert_eq!(systemtime_to_unix(half_before), (-1, 500_000_000));
+        assert_eq!(unix_to_systemtime(-1, 500_000_000), Some(half_before));
+        assert_eq!(systemtime_to_unix(UNIX_EPOCH), (0, 0));
+    }
+
+    #[test]
+    fn out_of_range_unix_time_does_not_panic() {
+        let _ = unix_to_systemtime(i64::MAX, 999_999_999);
+        let _ = unix_to_systemtime(i64::MIN, 0);
+    }
+
+    #[test]
+    fn filetime_of_the_epoch() {
+        assert_eq!(unix_to_filetime(0, 0), FILETIME_UNIX_DIFF);
+        assert_eq!(filetime_to_unix(FILETIME_UNIX_DIFF), (0, 0));
+        assert_eq!(filetime_to_systemtime(FILETIME_UNIX_DIFF), Some(UNIX_EPOCH));
+        assert_eq!(systemtime_to_filetime(UNIX_EPOCH), FILETIME_UNIX_DIFF);
+    }
+
+    #[test]
+    fn filetime_before_the_epoch() {
+        assert_eq!(
+            unix_to_filetime(-1, 500_000_000),
+            FILETIME_UNIX_DIFF - 5_000_000
+        );
+        assert_eq!(
+            filetime_to_unix(FILETIME_UNIX_DIFF - 5_000_000),
+            (-1, 500_000_000)
+        );
+        assert_eq!(filetime_to_unix(0), (-11_644_473_600, 0));
+    }
+
+    #[test]
+    fn filetime_truncates_and_clamps() {
+        assert_eq!(unix_to_filetime(0, 199), FILETIME_UNIX_DIFF + 1);
+        assert_eq!(unix_to_filetime(i64::MIN, 0), 0);
+        assert_eq!(unix_to_filetime(i64::MAX, 0), u64::MAX);
+        let (secs, nanos) = filetime_to_unix(u64::MAX);
+        assert_eq!(unix_to_filetime(secs, nanos), u64::MAX);
+    }
+
+    #[test]
+    fn dos_time_of_1980() {
+        let start = utc(1980, 1, 1, 0);
+        assert_eq!(systemtime_to_dos_time(start), DOS_TIME_MIN);
+        assert_eq!(dos_time_to_systemtime(DOS_TIME_MIN), Some(start));
+    }
+
+    #[test]
+    fn dos_time_rounds_up_to_two_seconds() {
+        let start = utc(1980, 1, 1, 0);
+        assert_eq!(
+            systemtime_to_dos_time(start + Duration::from_millis(500)),
+            DOS_TIME_MIN + 1
+        );
+        assert_eq!(
+            systemtime_to_dos_time(start + Duration::from_secs(1)),
+            DOS_TIME_MIN + 1
+        );
+        assert_eq!(
+            systemtime_to_dos_time(start + Duration::from_secs(2)),
+            DOS_TIME_MIN + 1
+        );
+    }
+
+    #[test]
+    fn dos_time_clamps_to_its_range() {
+        assert_eq!(
+            systemtime_to_dos_time(utc(1979, 12, 31, 86_399)),
+            DOS_TIME_MIN
+        );
+        assert_eq!(systemtime_to_dos_time(UNIX_EPOCH), DOS_TIME_MIN);
+        assert_eq!(
+            systemtime_to_dos_time(utc(2107, 12, 31, 86_398)),
+            DOS_TIME_MAX
+        );
+        // Rounding up 23:59:59 crosses into 2108
+        assert_eq!(
+            systemtime_to_dos_time(utc(2107, 12, 31, 86_399)),
+            DOS_TIME_MAX
+        );
+        assert_eq!(systemtime_to_dos_time(utc(2200, 1, 1, 0)), DOS_TIME_MAX);
+    }
+
+    #[test]
+    fn dos_time_round_trip() {
+        let time = utc(2024, 2, 29, 13 * 3_600 + 37 * 60 + 42);
+        let dos = systemtime_to_dos_time(time);
+        assert_eq!(dos_time_to_systemtime(dos), Some(time));
+        assert_eq!(
+            dos_time_to_systemtime(DOS_TIME_MAX),
+            Some(utc(2107, 12, 31, 86_398))
+        );
+    }
+
+    #[test]
+    fn invalid_dos_time_is_rejected() {
+        // Month 0
+        assert_eq!(dos_time_to_systemtime(0x0001_0000), None);
+        // 24:00
+        assert_eq!(dos_time_to_systemtime(DOS_TIME_MIN | 24 << 11), None);
+    }
+
+    #[test]
+    fn dos_dates_past_the_end_of_the_month_are_rejected() {
+        let dos_date =
+            |year: u32, month: u32, day: u32| ((year - 1980) << 9 | month << 5 | day) << 16;
+
+        assert_eq!(dos_time_to_systemtime(dos_date(2023, 2, 30)), None);
+        assert_eq!(dos_time_to_systemtime(dos_date(2023, 2, 29)), None);
+        assert_eq!(dos_time_to_systemtime(dos_date(2023, 4, 31)), None);
+        assert_eq!(dos_time_to_systemtime(dos_date(2100, 2, 29)), None);
+        assert_eq!(
+            dos_time_to_systemtime(dos_date(2024, 2, 29)),
+            Some(utc(2024, 2, 29, 0))
+        );
+        assert_eq!(
+            dos_time_to_systemtime(dos_date(2000, 2, 29)),
+            Some(utc(2000, 2, 29, 0))
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! The few Win32 items the COM bridge needs.
+//!
+//! On Windows these come from the `windows` crate. Elsewhere the OLE string
+//! functions are implemented on the Rust heap, so the bridge can be driven by
+//! the in-process host in [`crate::testing`] on any OS.
+
+pub use windows_core::{GUID, HRESULT};
+
+#[cfg(windows)]
+pub use windows::Win32::Foundation::{
+    CLASS_E_CLASSNOTAVAILABLE, E_ABORT, E_FAIL, E_INVALIDARG, E_NOINTERFACE, E_NOTIMPL,
+    E_OUTOFMEMORY, E_POINTER, S_FALSE, S_OK, SysAllocStringByteLen,
+};
+#[cfg(windows)]
+pub use windows::core::BSTR;
+
+#[cfg(not(windows))]
+pub use portable::*;
+
+#[cfg(not(windows))]
+mod portable {
+    use std::alloc::{Layout, alloc, dealloc, handle_alloc_error};
+    use std::fmt;
+    use std::ops::Deref;
+
+    use super::HRESULT;
+
+    pub const S_OK: HRESULT = HRESULT(0);
+    pub const S_FALSE: HRESULT = HRESULT(1);
+    pub const E_NOTIMPL: HRESULT = HRESULT(0x8000_4001_u32 as i32);
+    pub const E_NOINTERFACE: HRESULT = HRESULT(0x8000_4002_u32 as i32);
+    pub const E_POINTER: HRESULT = HRESULT(0x8000_4003_u32 as i32);
+    pub const E_ABORT: HRESULT = HRESULT(0x8000_4004_u32 as i32);
+    pub const E_FAIL: HRESULT = HRESULT(0x8000_4005_u32 as i32);
+    pub const E_OUTOFMEMORY: HRESULT = HRESULT(0x8007_000E_u32 as i32);
+    pub const E_INVALIDARG: HRESULT = HRESULT(0x8007_0057_u32 as i32);
+    pub const CLASS_E_CLASSNOTAVAILABLE: HRESULT = HRESULT(0x8004_0111_u32 as i32);
+
+    /// Size of the byte length stored in front of the string data.
+    const PREFIX: usize = std::mem::size_of::<u32>();
+
+    /// Allocation holding a string of `byte_len` bytes: the length prefix,
+    /// the data and a wide null terminator.
+    fn layout(byte_len: usize) -> Layout {
+        Layout::from_size_align(PREFIX + byte_len + 2, PREFIX).expect("BSTR too large")
+    }
+
+    /// Length-prefixed UTF-16 string, laid out like an OLE `BSTR`.
+    ///
+    /// The pointer addresses the string data; its length in bytes is stored
+    /// in the four bytes before it.
+    #[repr(transparent)]
+    pub struct BSTR(*const u16);
+
+    impl BSTR {
+        /// An empty (null) string.
+        pub const fn new() -> Self {
+            Self(std::ptr::null())
+        }
+
+        /// Allocate a string holding `value`.
+        pub fn from_wide(value: &[u16]) -> Self {
+            if value.is_empty() {
+                return Self::new();
+            }
+            let bytes: Vec<u8> = value.iter().flat_map(|c| c.to_ne_bytes()).collect();
+            unsafe { SysAllocStringByteLen(Some(&bytes)) }
+        }
+
+        /// Take ownership of a string allocated by [`SysAllocStringByteLen`].
+        ///
+        /// # Safety
+        /// `raw` must be null or come from this module's allocator.
+        pub unsafe fn from_raw(raw: *const u16) -> Self {
+            Self(raw)
+        }
+
+        /// Give up ownership of the string.
+        pub fn into_raw(self) -> *const u16 {
+            let raw = self.0;
+            std::mem::forget(self);
+            raw
+        }
+
+        /// Length of the data in bytes.
+        fn byte_len(&self) -> usize {
+            if self.0.is_null() {
+                return 0;
+            }
+            unsafe { (self.0 as *const u8).sub(PREFIX).cast::<u32>().read() as usize }
+        }
+    }
+
+    impl Default for BSTR {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Deref for BSTR {
+        type Target = [u16];
+
+        fn deref(&self) -> &[u16] {
+            if self.0.is_null() {
+                return &[];
+            }
+            unsafe { std::slice::from_raw_parts(self.0, self.byte_len() / 2) }
+        }
+    }
+
+    impl Drop for BSTR {
+        fn drop(&mut self) {
+            if !self.0.is_null() {
+                let layout = layout(self.byte_len());
+                unsafe { dealloc((self.0 as *mut u8).sub(PREFIX), layout) };
+            }
+        }
+    }
+
+    impl From<&str> for BSTR {
+        fn from(value: &str) -> Self {
+            Self::from_wide(&value.encode_utf16().collect::<Vec<_>>())
+        }
+    }
+
+    impl fmt::Display for BSTR {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(&String::from_utf16_lossy(self))
+        }
+    }
+
+    impl fmt::Debug for BSTR {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt::Debug::fmt(&String::from_utf16_lossy(self), f)
+        }
+    }
+
+    /// Allocate a string holding `bytes` as raw data, like `SysAllocStringByteLen`.
+    ///
+    /// # Safety
+    /// Always safe; `unsafe` to match the Win32 signature.
+    #[allow(non_snake_case)]
+    pub unsafe fn SysAllocStringByteLen(bytes: Option<&[u8]>) -> BSTR {
+        let bytes = bytes.unwrap_or_default();
+        let layout = layout(bytes.len());
+        unsafe {
+            let base = alloc(layout);
+            if base.is_null() {
+                handle_alloc_error(layout);
+            }
+            base.cast::<u32>()
+                .write(u32::try_from(bytes.len()).expect("BSTR too large"));
+            let data = base.add(PREFIX);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), data, bytes.len());
+            data.add(bytes.len())
+                .cast::<[u8; 2]>()
+                .write_unaligned([0, 0]);
+            BSTR(data as *const u16)
+        }
+    }
+}
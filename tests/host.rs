@@ -0,0 +1,1171 @@
+//! Drive a small sample format through the COM bridge with the test host.
+
+use std::io::{Read, Write};
+
+use sevenzip_plugin::prelude::*;
+use sevenzip_plugin::testing::{
+    CLASS_E_CLASSNOTAVAILABLE, E_ABORT, E_FAIL, E_INVALIDARG, E_NOTIMPL, HandlerPropId,
+    OperationResult, S_FALSE, S_OK, TestHost, TestPlugin, UpdateEntry,
+};
+
+const MAGIC: &[u8; 4] = b"TST1";
+
+/// Sample format: magic, an encryption flag, an entry count, then each
+/// entry's name, directory flag and data. With a password, entry data is
+/// XORed with it and a checksum of the password is stored after the flag.
+/// Opening and writing report progress once per entry.
+#[derive(Default)]
+struct Sample {
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    item: ArchiveItem,
+    data: Vec<u8>,
+}
+
+impl ArchiveFormat for Sample {
+    fn name() -> &'static str {
+        "Sample"
+    }
+
+    fn extension() -> &'static str {
+        "tst"
+    }
+
+    const CLASS_ID: [u8; 16] = [
+        0x5a, 0x6b, 0x41, 0x02, 0x9c, 0x1e, 0x4f, 0x3a, 0x8d, 0x27, 0x60, 0x11, 0xe4, 0x73, 0xb8,
+        0x05,
+    ];
+
+    fn signature() -> Option<&'static [u8]> {
+        Some(MAGIC)
+    }
+
+    fn supports_write() -> bool {
+        true
+    }
+
+    fn supports_update() -> bool {
+        true
+    }
+}
+
+fn password_check(password: &str) -> u32 {
+    password.bytes().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+fn xor(data: &mut [u8], password: &str) {
+    for (byte, key) in data.iter_mut().zip(password.bytes().cycle()) {
+        *byte ^= key;
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(Error::UnexpectedEof);
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn take_u32(data: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(take(data, 4)?.try_into().unwrap()))
+}
+
+impl ArchiveReader for Sample {
+    fn open(&mut self, reader: &mut dyn ReadSeek, size: u64, ctx: &dyn OpenContext) -> Result<()> {
+        self.open_with_password(reader, size, ctx, None)
+    }
+
+    fn open_with_password(
+        &mut self,
+        reader: &mut dyn ReadSeek,
+        size: u64,
+        ctx: &dyn OpenContext,
+        password_requester: Option<&dyn PasswordRequester>,
+    ) -> Result<()> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let mut data = buf.as_slice();
+
+        if !data.starts_with(MAGIC) {
+            return Err(Error::NotArchive);
+        }
+        take(&mut data, MAGIC.len())?;
+
+        let password = match take(&mut data, 1)?[0] {
+            0 => None,
+            _ => {
+                let check = take_u32(&mut data)?;
+                let password = password_requester
+                    .ok_or(Error::WrongPassword)?
+                    .get_password()?
+                    .ok_or(Error::WrongPassword)?;
+                if password_check(&password) != check {
+                    return Err(Error::WrongPassword);
+                }
+                Some(password)
+            }
+        };
+
+        let count = take_u32(&mut data)?;
+        ctx.set_total(Some(u64::from(count)), Some(size))?;
+        for done in 1..=u64::from(count) {
+            let name_len = take_u32(&mut data)? as usize;
+            let name = String::from_utf8(take(&mut data, name_len)?.to_vec())
+                .map_err(|_| Error::InvalidFormat("bad name".into()))?;
+            let is_dir = take(&mut data, 1)?[0] != 0;
+            let size = take_u32(&mut data)? as usize;
+            let mut contents = take(&mut data, size)?.to_vec();
+            if let Some(password) = &password {
+                xor(&mut contents, password);
+            }
+
+            self.entries.push(Entry {
+                item: ArchiveItem {
+                    name,
+                    size: size as u64,
+                    is_dir,
+                    encrypted: password.is_some(),
+                    ..Default::default()
+                },
+                data: contents,
+            });
+            ctx.set_completed(Some(done), None)?;
+        }
+        Ok(())
+    }
+
+    fn item_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn get_item(&self, index: usize) -> Option<&ArchiveItem> {
+        self.entries.get(index).map(|entry| &entry.item)
+    }
+
+    fn extract(&mut self, index: usize) -> Result<Vec<u8>> {
+        let count = self.entries.len();
+        self.entries
+            .get(index)
+            .map(|entry| entry.data.clone())
+            .ok_or(Error::IndexOutOfBounds { index, count })
+    }
+
+    fn close(&mut self) {
+        self.entries.clear();
+    }
+
+    fn archive_properties(&self) -> Vec<ArchiveProperty> {
+        // PhySize and a second comment repeat IDs the bridge already lists
+        vec![
+            ArchiveProperty::Comment("sample archive".into()),
+            ArchiveProperty::Other(PropId::PHY_SIZE, PropValue::U64(0)),
+            ArchiveProperty::Other(PropId::COMMENT, PropValue::String("shadowed".into())),
+        ]
+    }
+}
+
+impl ArchiveUpdater for Sample {
+    fn update_streaming(
+        &mut self,
+        existing: &mut dyn ReadSeek,
+        existing_size: u64,
+        updates: UpdatePlan<'_>,
+        writer: &mut dyn Write,
+        progress: Option<ProgressCallback<'_>>,
+    ) -> Result<u64> {
+        self.update_streaming_with_password(
+            existing,
+            existing_size,
+            updates,
+            writer,
+            progress,
+            None,
+        )
+    }
+
+    fn update_streaming_with_password(
+        &mut self,
+        _existing: &mut dyn ReadSeek,
+        _existing_size: u64,
+        updates: UpdatePlan<'_>,
+        writer: &mut dyn Write,
+        mut progress: Option<ProgressCallback<'_>>,
+        password_provider: Option<&dyn PasswordProvider>,
+    ) -> Result<u64> {
+        let password = match password_provider {
+            Some(provider) => provider.get_password()?,
+            None => None,
+        };
+
+        let mut out = MAGIC.to_vec();
+        match &password {
+            Some(password) => {
+                out.push(1);
+                out.extend_from_slice(&password_check(password).to_le_bytes());
+            }
+            None => out.push(0),
+        }
+        let total = updates.items.len() as u64;
+        out.extend_from_slice(&(total as u32).to_le_bytes());
+
+        for (done, update) in (1..).zip(updates.items) {
+            let (name, is_dir, mut data) = match update {
+                UpdateItem::CopyExisting {
+                    index, new_name, ..
+                } => {
+                    let entry = &self.entries[index];
+                    let name = new_name.unwrap_or_else(|| entry.item.name.clone());
+                    (name, entry.item.is_dir, entry.data.clone())
+                }
+                UpdateItem::AddNew { item, mut data } => {
+                    let mut contents = Vec::new();
+                    data.read_to_end(&mut contents)?;
+                    (item.name, item.is_dir, contents)
+                }
+            };
+            if let Some(password) = &password {
+                xor(&mut data, password);
+            }
+
+            out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.push(u8::from(is_dir));
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&data);
+
+            if let Some(progress) = progress.as_mut()
+                && !progress(done, total)
+            {
+                return Err(Error::Cancelled);
+            }
+        }
+
+        writer.write_all(&out)?;
+        Ok(out.len() as u64)
+    }
+}
+
+/// The sample format with an `extract_many` that finishes items it shouldn't.
+#[derive(Default)]
+struct Careless(Sample);
+
+impl ArchiveFormat for Careless {
+    fn name() -> &'static str {
+        "Careless"
+    }
+
+    fn extension() -> &'static str {
+        "tst"
+    }
+
+    const CLASS_ID: [u8; 16] = [
+        0x5a, 0x6b, 0x41, 0x02, 0x9c, 0x1e, 0x4f, 0x3a, 0x8d, 0x27, 0x60, 0x11, 0xe4, 0x73, 0xb8,
+        0x06,
+    ];
+}
+
+impl ArchiveReader for Careless {
+    fn open(&mut self, reader: &mut dyn ReadSeek, size: u64, ctx: &dyn OpenContext) -> Result<()> {
+        self.0.open(reader, size, ctx)
+    }
+
+    fn item_count(&self) -> usize {
+        self.0.item_count()
+    }
+
+    fn get_item(&self, index: usize) -> Option<&ArchiveItem> {
+        self.0.get_item(index)
+    }
+
+    fn extract(&mut self, index: usize) -> Result<Vec<u8>> {
+        self.0.extract(index)
+    }
+
+    fn extract_many(
+        &mut self,
+        _indices: &[usize],
+        sinks: &mut dyn ExtractSinks,
+        _password_requester: Option<&dyn PasswordRequester>,
+    ) -> Result<()> {
+        sinks.open(0)?;
+        sinks.finish(0, Ok(()))?;
+
+        assert!(sinks.finish(0, Ok(())).is_err(), "finished twice");
+        assert!(sinks.open(0).is_err(), "reopened a finished item");
+        assert!(
+            sinks.finish(2, Ok(())).is_err(),
+            "finished an unopened item"
+        );
+        assert!(sinks.open(7).is_err(), "opened an unrequested item");
+        Ok(())
+    }
+}
+
+/// The sample format counting the items it extracts, so the plugin changes
+/// while the host reads item properties from inside `extract_many`.
+#[derive(Default)]
+struct Tally {
+    sample: Sample,
+    extracted: usize,
+}
+
+impl ArchiveFormat for Tally {
+    fn name() -> &'static str {
+        "Tally"
+    }
+
+    fn extension() -> &'static str {
+        "tst"
+    }
+
+    const CLASS_ID: [u8; 16] = [
+        0x5a, 0x6b, 0x41, 0x02, 0x9c, 0x1e, 0x4f, 0x3a, 0x8d, 0x27, 0x60, 0x11, 0xe4, 0x73, 0xb8,
+        0x07,
+    ];
+}
+
+impl ArchiveReader for Tally {
+    fn open(&mut self, reader: &mut dyn ReadSeek, size: u64, ctx: &dyn OpenContext) -> Result<()> {
+        self.sample.open(reader, size, ctx)
+    }
+
+    fn item_count(&self) -> usize {
+        self.sample.item_count()
+    }
+
+    fn get_item(&self, index: usize) -> Option<&ArchiveItem> {
+        self.sample.get_item(index)
+    }
+
+    fn extract(&mut self, index: usize) -> Result<Vec<u8>> {
+        self.extracted += 1;
+        self.sample.extract(index)
+    }
+}
+
+/// A writer that reads the new items named `*.txt` and skips the others, and
+/// refuses to write an archive holding an item named `refused`, as if it
+/// used a method the writer doesn't support, or one named `corrupt`, as if
+/// its data failed a checksum.
+#[derive(Default)]
+struct Choosy(Sample);
+
+impl ArchiveFormat for Choosy {
+    fn name() -> &'static str {
+        "Choosy"
+    }
+
+    fn extension() -> &'static str {
+        "tst"
+    }
+
+    const CLASS_ID: [u8; 16] = [
+        0x5a, 0x6b, 0x41, 0x02, 0x9c, 0x1e, 0x4f, 0x3a, 0x8d, 0x27, 0x60, 0x11, 0xe4, 0x73, 0xb8,
+        0x08,
+    ];
+
+    fn supports_write() -> bool {
+        true
+    }
+}
+
+impl ArchiveReader for Choosy {
+    fn open(&mut self, reader: &mut dyn ReadSeek, size: u64, ctx: &dyn OpenContext) -> Result<()> {
+        self.0.open(reader, size, ctx)
+    }
+
+    fn item_count(&self) -> usize {
+        self.0.item_count()
+    }
+
+    fn get_item(&self, index: usize) -> Option<&ArchiveItem> {
+        self.0.get_item(index)
+    }
+
+    fn extract(&mut self, index: usize) -> Result<Vec<u8>> {
+        self.0.extract(index)
+    }
+}
+
+impl ArchiveUpdater for Choosy {
+    fn update_streaming(
+        &mut self,
+        _existing: &mut dyn ReadSeek,
+        _existing_size: u64,
+        updates: UpdatePlan<'_>,
+        writer: &mut dyn Write,
+        _progress: Option<ProgressCallback<'_>>,
+    ) -> Result<u64> {
+        let mut refused = false;
+        let mut corrupt = false;
+        let mut out = Vec::new();
+        for update in updates.items {
+            if let UpdateItem::AddNew { item, mut data } = update {
+                refused |= item.name == "refused";
+                corrupt |= item.name == "corrupt";
+                if item.name.ends_with(".txt") {
+                    data.read_to_end(&mut out)?;
+                }
+            }
+        }
+        if refused {
+            return Err(Error::UnsupportedMethod("refused".into()));
+        }
+        if corrupt {
+            return Err(Error::ChecksumMismatch);
+        }
+
+        writer.write_all(&out)?;
+        Ok(out.len() as u64)
+    }
+}
+
+/// The sample format failing on items by name: `crc`, `data`, `method` and
+/// `short` fail to extract, `panic` panics. An item named `headers` fails
+/// the open, one named `truncated` is reported as damaged.
+#[derive(Default)]
+struct Faulty(Sample);
+
+impl ArchiveFormat for Faulty {
+    fn name() -> &'static str {
+        "Faulty"
+    }
+
+    fn extension() -> &'static str {
+        "tst"
+    }
+
+    const CLASS_ID: [u8; 16] = [
+        0x5a, 0x6b, 0x41, 0x02, 0x9c, 0x1e, 0x4f, 0x3a, 0x8d, 0x27, 0x60, 0x11, 0xe4, 0x73, 0xb8,
+        0x09,
+    ];
+}
+
+impl Faulty {
+    fn has_item(&self, name: &str) -> bool {
+        self.0.entries.iter().any(|entry| entry.item.name == name)
+    }
+}
+
+impl ArchiveReader for Faulty {
+    fn open(&mut self, reader: &mut dyn ReadSeek, size: u64, ctx: &dyn OpenContext) -> Result<()> {
+        self.0.open(reader, size, ctx)?;
+        if self.has_item("headers") {
+            return Err(Error::InvalidFormat("damaged index".into()));
+        }
+        Ok(())
+    }
+
+    fn item_count(&self) -> usize {
+        self.0.item_count()
+    }
+
+    fn get_item(&self, index: usize) -> Option<&ArchiveItem> {
+        self.0.get_item(index)
+    }
+
+    fn extract(&mut self, index: usize) -> Result<Vec<u8>> {
+        match self.0.get_item(index).map(|item| item.name.as_str()) {
+            Some("crc") => Err(Error::ChecksumMismatch),
+            Some("data") => Err(Error::InvalidFormat("bad block".into())),
+            Some("method") => Err(Error::UnsupportedMethod("LZX".into())),
+            Some("short") => Err(Error::UnexpectedEof),
+            Some("panic") => panic!("decoder bug"),
+            _ => self.0.extract(index),
+        }
+    }
+
+    fn close(&mut self) {
+        self.0.close();
+    }
+
+    fn diagnostics(&self) -> ArchiveDiagnostics {
+        if !self.has_item("truncated") {
+            return ArchiveDiagnostics::default();
+        }
+        ArchiveDiagnostics {
+            errors: ArchiveErrorFlags::UNEXPECTED_END,
+            warnings: ArchiveErrorFlags::DATA_AFTER_END,
+            error: Some("index ends early".into()),
+            warning: Some("trailing garbage".into()),
+        }
+    }
+}
+
+/// Plugin-defined column of `Lazy` items.
+const ORIGIN: PropId = PropId::user_defined(0);
+
+/// The sample format answering `item_property()` from its entries instead of
+/// building `ArchiveItem`s. Items named `*.bad` claim a CRC their data
+/// doesn't have. Opening polls for cancellation first.
+#[derive(Default)]
+struct Lazy(Sample);
+
+impl ArchiveFormat for Lazy {
+    fn name() -> &'static str {
+        "Lazy"
+    }
+
+    fn extension() -> &'static str {
+        "tst"
+    }
+
+    const CLASS_ID: [u8; 16] = [
+        0x5a, 0x6b, 0x41, 0x02, 0x9c, 0x1e, 0x4f, 0x3a, 0x8d, 0x27, 0x60, 0x11, 0xe4, 0x73, 0xb8,
+        0x0a,
+    ];
+}
+
+impl ArchiveReader for Lazy {
+    fn open(&mut self, reader: &mut dyn ReadSeek, size: u64, ctx: &dyn OpenContext) -> Result<()> {
+        if ctx.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        self.0.open(reader, size, ctx)
+    }
+
+    fn item_count(&self) -> usize {
+        self.0.item_count()
+    }
+
+    fn item_property(&self, index: usize, id: PropId) -> Option<PropValue> {
+        let item = &self.0.entries.get(index)?.item;
+        match id {
+            PropId::PATH => Some(item.name.clone().into()),
+            PropId::SIZE => Some(item.size.into()),
+            PropId::IS_DIR => Some(item.is_dir.into()),
+            PropId::CRC if item.name.ends_with(".bad") => Some(0u32.into()),
+            ORIGIN => Some("lazy".into()),
+            _ => None,
+        }
+    }
+
+    fn item_properties(&self) -> Vec<PropertyInfo> {
+        vec![
+            PropertyInfo::new(PropId::PATH, PropType::String),
+            PropertyInfo::new(PropId::SIZE, PropType::U64),
+            PropertyInfo::named(ORIGIN, "Origin", PropType::String),
+        ]
+    }
+
+    fn extract(&mut self, index: usize) -> Result<Vec<u8>> {
+        self.0.extract(index)
+    }
+}
+
+/// A format with two signatures at offset 8, registered with the sample
+/// formats below. It never opens anything.
+#[derive(Default)]
+struct Signed;
+
+impl ArchiveFormat for Signed {
+    fn name() -> &'static str {
+        "Signed"
+    }
+
+    fn extension() -> &'static str {
+        "sgn"
+    }
+
+    const CLASS_ID: [u8; 16] = [
+        0x5a, 0x6b, 0x41, 0x02, 0x9c, 0x1e, 0x4f, 0x3a, 0x8d, 0x27, 0x60, 0x11, 0xe4, 0x73, 0xb8,
+        0x0b,
+    ];
+
+    fn signatures() -> &'static [&'static [u8]] {
+        &[b"SGN1", b"SGN2"]
+    }
+
+    fn signature_offset() -> u32 {
+        8
+    }
+
+    fn flags() -> FormatFlags {
+        FormatFlags::KEEP_NAME | FormatFlags::FIND_SIGNATURE
+    }
+}
+
+impl ArchiveReader for Signed {
+    fn open(
+        &mut self,
+        _reader: &mut dyn ReadSeek,
+        _size: u64,
+        _ctx: &dyn OpenContext,
+    ) -> Result<()> {
+        Err(Error::NotArchive)
+    }
+
+    fn item_count(&self) -> usize {
+        0
+    }
+
+    fn extract(&mut self, index: usize) -> Result<Vec<u8>> {
+        Err(Error::IndexOutOfBounds { index, count: 0 })
+    }
+}
+
+sevenzip_plugin::register_formats!(Sample: updatable, Tally, Signed);
+
+/// The exports generated by `register_formats!` above.
+fn plugin() -> TestPlugin {
+    TestPlugin::new(
+        CreateObject,
+        GetNumberOfFormats,
+        GetHandlerProperty2,
+        GetIsArc,
+    )
+}
+
+/// An archive of files named `names`, each holding `data`.
+fn archive_of(names: &[&str]) -> Vec<u8> {
+    TestHost::<Sample>::updatable()
+        .update(
+            names
+                .iter()
+                .map(|name| UpdateEntry::file(*name, b"data".to_vec()))
+                .collect(),
+        )
+        .expect("create archive")
+}
+
+fn sample_archive() -> Vec<u8> {
+    TestHost::<Sample>::updatable()
+        .update(vec![
+            UpdateEntry::file("a.txt", b"hello".to_vec()),
+            UpdateEntry::dir("dir"),
+            UpdateEntry::file("dir/b.bin", vec![0, 1, 2, 3]),
+        ])
+        .expect("create archive")
+}
+
+/// A host with an archive holding `a.txt`, `dir/` and `dir/b.bin`.
+fn sample_host() -> TestHost<Sample> {
+    let mut host = TestHost::<Sample>::updatable();
+    host.open(sample_archive()).expect("open archive");
+    host
+}
+
+#[test]
+fn lists_items() {
+    let host = sample_host();
+
+    assert_eq!(host.item_count(), Ok(3));
+    assert_eq!(
+        host.property(0, PropId::PATH),
+        Ok(Some(PropValue::String("a.txt".into())))
+    );
+    assert_eq!(host.property(0, PropId::SIZE), Ok(Some(PropValue::U64(5))));
+    assert_eq!(
+        host.property(1, PropId::IS_DIR),
+        Ok(Some(PropValue::Bool(true)))
+    );
+
+    let columns = host.properties().expect("item properties");
+    assert!(columns.iter().any(|column| column.id == PropId::PATH));
+}
+
+#[test]
+fn lists_archive_properties_once() {
+    let host = sample_host();
+
+    let ids: Vec<PropId> = host
+        .archive_properties()
+        .expect("archive properties")
+        .iter()
+        .map(|property| property.id)
+        .collect();
+    assert_eq!(ids, [PropId::PHY_SIZE, PropId::COMMENT]);
+    assert_eq!(
+        host.archive_property(PropId::COMMENT),
+        Ok(Some(PropValue::String("sample archive".into())))
+    );
+}
+
+#[test]
+fn extracts_items() {
+    let mut host = sample_host();
+    let report = host.extract_all();
+
+    assert_eq!(report.result, sevenzip_plugin::testing::S_OK);
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.items.len(), 3);
+    assert_eq!(report.data(0), Some(&b"hello"[..]));
+    assert_eq!(report.data(2), Some(&[0, 1, 2, 3][..]));
+    assert_eq!(
+        report.item(2).and_then(|item| item.path.as_deref()),
+        Some("dir/b.bin")
+    );
+    assert!(
+        report
+            .items
+            .iter()
+            .all(|item| item.result == Some(OperationResult::Ok))
+    );
+}
+
+#[test]
+fn extracts_selected_items() {
+    let mut host = sample_host();
+    let report = host.extract(&[2]);
+
+    assert_eq!(report.items.len(), 1);
+    assert_eq!(report.result_of(2), Some(OperationResult::Ok));
+    assert_eq!(report.data(2), Some(&[0, 1, 2, 3][..]));
+}
+
+#[test]
+fn rejects_finishing_items_that_are_not_open() {
+    let mut host = TestHost::<Careless>::new();
+    host.open(sample_archive()).expect("open archive");
+    let report = host.extract(&[0, 2]);
+
+    assert_eq!(report.result, sevenzip_plugin::testing::S_OK);
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.items.len(), 2);
+    assert_eq!(report.result_of(0), Some(OperationResult::Ok));
+    assert_eq!(report.result_of(2), Some(OperationResult::Unavailable));
+}
+
+#[test]
+fn reads_properties_while_extracting() {
+    let mut host = TestHost::<Tally>::new();
+    host.open(sample_archive()).expect("open archive");
+    let report = host.extract_all();
+
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(
+        report.item(2).and_then(|item| item.path.as_deref()),
+        Some("dir/b.bin")
+    );
+    assert_eq!(report.data(0), Some(&b"hello"[..]));
+    assert_eq!(host.item_count(), Ok(3));
+}
+
+#[test]
+fn tests_items_without_output() {
+    let mut host = sample_host();
+    let report = host.test_all();
+
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.items.len(), 3);
+    assert!(report.items.iter().all(|item| item.data.is_empty()));
+    assert!(
+        report
+            .items
+            .iter()
+            .all(|item| item.result == Some(OperationResult::Ok))
+    );
+}
+
+#[test]
+fn updates_existing_archive() {
+    let mut host = sample_host();
+    let archive = host
+        .update(vec![
+            UpdateEntry::rename(0, "renamed.txt"),
+            UpdateEntry::keep(2),
+            UpdateEntry::file("new.txt", b"new".to_vec()),
+        ])
+        .expect("update archive");
+
+    host.open(archive).expect("open updated archive");
+    assert_eq!(host.item_count(), Ok(3));
+    assert_eq!(
+        host.property(0, PropId::PATH),
+        Ok(Some(PropValue::String("renamed.txt".into())))
+    );
+
+    let report = host.extract_all();
+    assert_eq!(report.data(0), Some(&b"hello"[..]));
+    assert_eq!(report.data(1), Some(&[0, 1, 2, 3][..]));
+    assert_eq!(report.data(2), Some(&b"new"[..]));
+}
+
+#[test]
+fn reports_items_read_by_an_update() {
+    let mut host = TestHost::<Choosy>::updatable();
+    host.update(vec![
+        UpdateEntry::file("a.txt", b"read".to_vec()),
+        UpdateEntry::file("b.bin", b"skipped".to_vec()),
+    ])
+    .expect("update archive");
+
+    // The skipped item is neither opened nor reported
+    assert_eq!(host.update_results(), [OperationResult::Ok]);
+}
+
+#[test]
+fn reports_nothing_when_an_update_fails() {
+    let mut host = TestHost::<Choosy>::updatable();
+    let result = host.update(vec![
+        UpdateEntry::file("a.txt", b"read".to_vec()),
+        UpdateEntry::file("refused", b"skipped".to_vec()),
+    ]);
+
+    // The plugin's UnsupportedMethod error becomes E_NOTIMPL
+    assert_eq!(result, Err(E_NOTIMPL));
+    assert!(host.update_results().is_empty());
+}
+
+#[test]
+fn fails_updates_with_damaged_data() {
+    let mut host = TestHost::<Choosy>::updatable();
+    let result = host.update(vec![UpdateEntry::file("corrupt", b"data".to_vec())]);
+
+    // Data and CRC errors have no HRESULT of their own
+    assert_eq!(result, Err(E_FAIL));
+}
+
+#[test]
+fn rejects_other_data() {
+    let mut host = TestHost::<Sample>::new();
+
+    assert_eq!(host.open(b"not an archive".to_vec()), Err(S_FALSE));
+}
+
+#[test]
+fn asks_for_password() {
+    let mut host = TestHost::<Sample>::updatable();
+    host.set_password(Some("secret"));
+    let archive = host
+        .update(vec![UpdateEntry::file("a.txt", b"hidden".to_vec())])
+        .expect("create encrypted archive");
+    assert_eq!(host.password_requests(), 1);
+
+    host.open(archive.clone()).expect("open with password");
+    assert_eq!(host.password_requests(), 2);
+    assert_eq!(
+        host.property(0, PropId::ENCRYPTED),
+        Ok(Some(PropValue::Bool(true)))
+    );
+    assert_eq!(host.extract_all().data(0), Some(&b"hidden"[..]));
+    host.close().expect("close");
+
+    host.set_password(Some("wrong"));
+    assert!(host.open(archive.clone()).is_err());
+
+    // Without a password the host cancels the prompt
+    host.set_password(None);
+    assert!(host.open(archive).is_err());
+}
+
+#[test]
+fn reports_open_progress() {
+    let archive = sample_archive();
+    let size = archive.len() as u64;
+    let host = {
+        let mut host = TestHost::<Sample>::new();
+        host.open(archive).expect("open archive");
+        host
+    };
+
+    let progress = host.open_progress();
+    assert_eq!(progress.total_files, Some(3));
+    assert_eq!(progress.total_bytes, Some(size));
+    assert_eq!(progress.completed_files, Some(3));
+    assert_eq!(progress.completed_bytes, None);
+    assert_eq!(progress.calls, 4);
+}
+
+#[test]
+fn cancels_open_from_progress() {
+    let mut host = TestHost::<Sample>::new();
+    host.cancel_after(Some(2));
+
+    // The third call, reporting the second entry, is cancelled
+    assert_eq!(host.open(sample_archive()), Err(E_ABORT));
+    assert_eq!(host.open_progress().completed_files, Some(2));
+}
+
+#[test]
+fn cancels_open_when_polled() {
+    let mut host = TestHost::<Lazy>::new();
+    host.cancel_after(Some(0));
+
+    assert_eq!(host.open(sample_archive()), Err(E_ABORT));
+    // is_cancelled() asks through SetCompleted without any counts
+    let progress = host.open_progress();
+    assert_eq!(progress.calls, 1);
+    assert_eq!(progress.completed_files, None);
+}
+
+#[test]
+fn cancels_extract_between_items() {
+    let mut host = sample_host();
+    // SetTotal, then SetCompleted after the first item
+    host.cancel_after(Some(1));
+    let report = host.extract_all();
+
+    assert_eq!(report.result, E_ABORT);
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.items.len(), 1);
+    assert_eq!(report.result_of(0), Some(OperationResult::Ok));
+}
+
+#[test]
+fn cancels_update_from_progress() {
+    let mut host = TestHost::<Sample>::updatable();
+    // SetTotal, then the plugin's first progress report
+    host.cancel_after(Some(1));
+    let result = host.update(vec![
+        UpdateEntry::file("a.txt", b"read".to_vec()),
+        UpdateEntry::file("b.txt", b"not read".to_vec()),
+    ]);
+
+    // Items read before the cancel are not reported either
+    assert_eq!(result, Err(E_ABORT));
+    assert!(host.update_results().is_empty());
+}
+
+#[test]
+fn reports_item_errors_as_operation_results() {
+    let mut host = TestHost::<Faulty>::new();
+    host.open(archive_of(&["ok", "crc", "data", "method", "short"]))
+        .expect("open archive");
+    let report = host.extract_all();
+
+    assert_eq!(report.result, S_OK);
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.result_of(0), Some(OperationResult::Ok));
+    assert_eq!(report.result_of(1), Some(OperationResult::CrcError));
+    assert_eq!(report.result_of(2), Some(OperationResult::DataError));
+    assert_eq!(
+        report.result_of(3),
+        Some(OperationResult::UnsupportedMethod)
+    );
+    assert_eq!(report.result_of(4), Some(OperationResult::UnexpectedEnd));
+}
+
+#[test]
+fn reports_why_an_open_failed() {
+    let mut host = TestHost::<Faulty>::new();
+
+    assert_eq!(host.open(archive_of(&["headers"])), Err(S_FALSE));
+    assert_eq!(
+        host.archive_property(PropId::ERROR_FLAGS),
+        Ok(Some(PropValue::U32(
+            ArchiveErrorFlags::HEADERS_ERROR.bits()
+        )))
+    );
+
+    // Data of another format is "not an archive"
+    assert_eq!(host.open(b"not an archive".to_vec()), Err(S_FALSE));
+    assert_eq!(
+        host.archive_property(PropId::ERROR_FLAGS),
+        Ok(Some(PropValue::U32(1)))
+    );
+}
+
+#[test]
+fn reports_archive_diagnostics() {
+    let mut host = TestHost::<Faulty>::new();
+    host.open(archive_of(&["truncated"])).expect("open archive");
+
+    assert_eq!(
+        host.archive_property(PropId::ERROR_FLAGS),
+        Ok(Some(PropValue::U32(
+            ArchiveErrorFlags::UNEXPECTED_END.bits()
+        )))
+    );
+    assert_eq!(
+        host.archive_property(PropId::WARNING_FLAGS),
+        Ok(Some(PropValue::U32(
+            ArchiveErrorFlags::DATA_AFTER_END.bits()
+        )))
+    );
+    assert_eq!(
+        host.archive_property(PropId::ERROR),
+        Ok(Some(PropValue::String("index ends early".into())))
+    );
+    assert_eq!(
+        host.archive_property(PropId::WARNING),
+        Ok(Some(PropValue::String("trailing garbage".into())))
+    );
+
+    // A healthy archive reports nothing
+    host.close().expect("close");
+    host.open(archive_of(&["ok"])).expect("open archive");
+    assert_eq!(host.archive_property(PropId::ERROR_FLAGS), Ok(None));
+    assert_eq!(host.archive_property(PropId::WARNING), Ok(None));
+}
+
+#[test]
+fn recovers_from_a_panic() {
+    let archive = archive_of(&["ok", "panic", "after"]);
+    let mut host = TestHost::<Faulty>::new();
+    host.open(archive.clone()).expect("open archive");
+    let report = host.extract_all();
+
+    // The unfinished items fail, and the handler closes the archive
+    assert_eq!(report.result, S_OK);
+    assert_eq!(report.result_of(0), Some(OperationResult::Ok));
+    assert_eq!(report.result_of(1), Some(OperationResult::DataError));
+    assert_eq!(report.result_of(2), Some(OperationResult::DataError));
+    assert_eq!(host.item_count(), Ok(0));
+
+    host.open(archive).expect("reopen archive");
+    let report = host.extract(&[2]);
+    assert_eq!(report.result_of(2), Some(OperationResult::Ok));
+    assert_eq!(report.data(2), Some(&b"data"[..]));
+}
+
+#[test]
+fn reads_items_through_item_property() {
+    let mut host = TestHost::<Lazy>::new();
+    host.open(sample_archive()).expect("open archive");
+
+    assert_eq!(
+        host.property(2, PropId::PATH),
+        Ok(Some(PropValue::String("dir/b.bin".into())))
+    );
+    assert_eq!(host.property(0, PropId::SIZE), Ok(Some(PropValue::U64(5))));
+    assert_eq!(host.property(0, PropId::MTIME), Ok(None));
+
+    let report = host.extract_all();
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(
+        report.item(2).and_then(|item| item.path.as_deref()),
+        Some("dir/b.bin")
+    );
+    assert_eq!(report.data(0), Some(&b"hello"[..]));
+}
+
+#[test]
+fn tests_items_against_their_crc() {
+    let mut host = TestHost::<Lazy>::new();
+    host.open(archive_of(&["good.txt", "broken.bad"]))
+        .expect("open archive");
+    let report = host.test_all();
+
+    assert_eq!(report.result, S_OK);
+    assert_eq!(report.result_of(0), Some(OperationResult::Ok));
+    assert_eq!(report.result_of(1), Some(OperationResult::CrcError));
+}
+
+#[test]
+fn lists_custom_item_columns() {
+    let mut host = TestHost::<Lazy>::new();
+    host.open(sample_archive()).expect("open archive");
+
+    let columns = host.properties().expect("item properties");
+    let ids: Vec<PropId> = columns.iter().map(|column| column.id).collect();
+    assert_eq!(ids, [PropId::PATH, PropId::SIZE, ORIGIN]);
+    assert_eq!(columns[0].name, None);
+    assert_eq!(columns[2].name.as_deref(), Some("Origin"));
+
+    assert_eq!(
+        host.property(1, ORIGIN),
+        Ok(Some(PropValue::String("lazy".into())))
+    );
+}
+
+#[test]
+fn lists_registered_formats() {
+    let plugin = plugin();
+
+    assert_eq!(plugin.format_count(), Ok(3));
+    assert_eq!(
+        plugin.handler_property(1, HandlerPropId::Name),
+        Ok(Some(PropValue::String("Tally".into())))
+    );
+    assert_eq!(
+        plugin.handler_property(2, HandlerPropId::ClassId),
+        Ok(Some(PropValue::Bytes(Signed::CLASS_ID.to_vec())))
+    );
+    assert_eq!(
+        plugin.handler_property(0, HandlerPropId::Update),
+        Ok(Some(PropValue::Bool(true)))
+    );
+    assert_eq!(
+        plugin.handler_property(1, HandlerPropId::Update),
+        Ok(Some(PropValue::Bool(false)))
+    );
+    assert_eq!(
+        plugin.handler_property(3, HandlerPropId::Name),
+        Err(E_INVALIDARG)
+    );
+}
+
+#[test]
+fn reports_signatures_and_flags() {
+    let plugin = plugin();
+
+    assert_eq!(
+        plugin.handler_property(0, HandlerPropId::Signature),
+        Ok(Some(PropValue::Bytes(MAGIC.to_vec())))
+    );
+    assert_eq!(
+        plugin.handler_property(0, HandlerPropId::MultiSignature),
+        Ok(None)
+    );
+
+    // Several signatures go in MultiSignature, each after its length
+    assert_eq!(
+        plugin.handler_property(2, HandlerPropId::Signature),
+        Ok(None)
+    );
+    assert_eq!(
+        plugin.handler_property(2, HandlerPropId::MultiSignature),
+        Ok(Some(PropValue::Bytes(b"\x04SGN1\x04SGN2".to_vec())))
+    );
+    assert_eq!(
+        plugin.handler_property(2, HandlerPropId::SignatureOffset),
+        Ok(Some(PropValue::U32(8)))
+    );
+    assert_eq!(
+        plugin.handler_property(2, HandlerPropId::KeepName),
+        Ok(Some(PropValue::Bool(true)))
+    );
+
+    // kMultiSignature (1 << 4) is added for formats with several signatures
+    let flags = (FormatFlags::KEEP_NAME | FormatFlags::FIND_SIGNATURE).bits() | 1 << 4;
+    assert_eq!(
+        plugin.handler_property(2, HandlerPropId::Flags),
+        Ok(Some(PropValue::U32(flags)))
+    );
+    assert_eq!(
+        plugin.handler_property(0, HandlerPropId::Flags),
+        Ok(Some(PropValue::U32(0)))
+    );
+}
+
+#[test]
+fn checks_headers_with_is_arc() {
+    let plugin = plugin();
+
+    assert_eq!(plugin.is_arc(0, b"TST1\0"), Ok(Some(Detection::Yes)));
+    assert_eq!(plugin.is_arc(0, b"TS"), Ok(Some(Detection::NeedMoreData)));
+    assert_eq!(plugin.is_arc(0, b"PK\x03\x04"), Ok(Some(Detection::No)));
+
+    // Signed's signatures start at offset 8
+    assert_eq!(plugin.is_arc(2, b"12345678SGN2"), Ok(Some(Detection::Yes)));
+    assert_eq!(plugin.is_arc(2, b"SGN2"), Ok(Some(Detection::NeedMoreData)));
+    assert_eq!(plugin.is_arc(2, b"12345678SGN3"), Ok(Some(Detection::No)));
+
+    assert_eq!(plugin.is_arc(3, b"TST1"), Err(E_INVALIDARG));
+}
+
+#[test]
+fn creates_handlers_by_class_id() {
+    let plugin = plugin();
+
+    let mut tally = plugin.host::<Tally>().expect("create Tally");
+    tally.open(sample_archive()).expect("open archive");
+    assert_eq!(tally.extract(&[0]).data(0), Some(&b"hello"[..]));
+
+    // Signed's handler rejects the same data
+    let mut signed = plugin.host::<Signed>().expect("create Signed");
+    assert_eq!(signed.open(sample_archive()), Err(S_FALSE));
+
+    assert!(matches!(
+        plugin.host::<Careless>(),
+        Err(CLASS_E_CLASSNOTAVAILABLE)
+    ));
+}